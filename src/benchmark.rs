@@ -0,0 +1,101 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    server::{
+        commands::TrackCommand,
+        parser::{FromResponse, SectionRecord},
+        CommandQueue, Endpoint,
+    },
+    utils::UnitResult,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+//measures round-trip latency of section-set commands against a running server,
+//first one at a time and then as a single burst, like a slider being dragged
+pub fn run(mut queue: CommandQueue, endpoint: &Endpoint, count: usize) -> UnitResult {
+    //every command has to reach the server, or there's nothing to measure
+    queue.set_coalescing(false);
+    queue.connect(endpoint)?;
+    let sections = queue.call(&TrackCommand::SectionList.to_string(), TIMEOUT)?;
+    let original = Vec::<SectionRecord>::from_response(&sections)?
+        .into_iter()
+        .next()
+        .ok_or("The track needs at least one section.")?;
+    //alternate between two lengths so every command is a real change
+    let setters = (0..count)
+        .map(|i| {
+            TrackCommand::SectionSet {
+                index: 0,
+                length: original.length + (i % 2) as i32,
+                curve: original.curve,
+                slope: original.slope,
+                split: original.split,
+            }
+            .to_string()
+        })
+        .collect::<Vec<String>>();
+
+    let mut sequential = Vec::with_capacity(count);
+    let start = Instant::now();
+    for command in &setters {
+        let t = Instant::now();
+        queue.call(command, TIMEOUT)?;
+        sequential.push(t.elapsed());
+    }
+    report("sequential", &mut sequential, start.elapsed());
+
+    let mut burst = Vec::with_capacity(count);
+    let start = Instant::now();
+    let mut pending = setters
+        .iter()
+        .map(|command| queue.send(command))
+        .collect::<HashSet<_>>();
+    while !pending.is_empty() {
+        if start.elapsed() > TIMEOUT {
+            return Err("Timed out waiting for the burst to finish.".into());
+        }
+        queue.wait(TIMEOUT)?;
+        while let Some((tickets, _)) = queue.receive() {
+            for ticket in tickets {
+                if pending.remove(&ticket) {
+                    burst.push(start.elapsed());
+                }
+            }
+        }
+    }
+    report("burst", &mut burst, start.elapsed());
+
+    let restore = TrackCommand::SectionSet {
+        index: 0,
+        length: original.length,
+        curve: original.curve,
+        slope: original.slope,
+        split: original.split,
+    };
+    queue.call(&restore.to_string(), TIMEOUT)?;
+    queue.disconnect()
+}
+
+fn report(name: &str, samples: &mut [Duration], total: Duration) {
+    if samples.is_empty() {
+        return;
+    }
+    samples.sort();
+    let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+    let rate = samples.len() as f64 / total.as_secs_f64();
+    println!(
+        "{:10} {} commands in {:.1?} ({:.0}/s) | min {:.2?} | p50 {:.2?} | p95 {:.2?} | max {:.2?}",
+        name,
+        samples.len(),
+        total,
+        rate,
+        samples[0],
+        percentile(50),
+        percentile(95),
+        samples[samples.len() - 1]
+    );
+}
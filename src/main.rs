@@ -1,49 +1,95 @@
-use std::{env, process};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal},
+    path::PathBuf,
+    process,
+    time::Duration,
+};
 
 use egui::ViewportBuilder;
+use mock::MockServer;
+use proxy::Proxy;
 use screen::Screen;
+use server::{
+    record::{self, Recorder},
+    tokenize, CommandQueue, Endpoint, KeepAlive, ServerProcess, DEFAULT_HOST,
+};
 use shell::Shell;
-use utils::UnitResult;
+use utils::{GenericResult, UnitResult};
 
+mod benchmark;
+mod mock;
+mod proxy;
 mod screen;
 mod server;
 mod shell;
 mod utils;
 
 const DEFAULT_PORT: u16 = 33760;
+const DEFAULT_PROXY_PORT: u16 = 33761;
+const DEFAULT_BENCHMARK_SIZE: usize = 200;
 const PROGRAM_NAME: &str = env!("CARGO_PKG_NAME");
 const PROGRAM_VERSION: &str = env!("CARGO_PKG_VERSION");
 const WINDOW_SIZE: (f32, f32) = (1280.0, 720.0);
 
+enum Mode {
+    Gui,
+    Shell,
+    Mock,
+    Benchmark(usize),
+    ViewLog(PathBuf),
+    Proxy(Endpoint),
+}
+
+struct Options {
+    mode: Mode,
+    endpoint: Endpoint,
+    record: Option<PathBuf>,
+    stats_csv: Option<PathBuf>,
+    max_frame_size: Option<usize>,
+    keepalive: KeepAlive,
+    server_cmd: Option<Vec<String>>,
+    restart_server: bool,
+    script: Option<PathBuf>,
+    exec: Vec<String>,
+    fail_fast: bool,
+}
+
 fn main() -> eframe::Result<()> {
-    let (shell, port) = parse_command_line_args(env::args().collect());
-    if shell {
-        if let Err(msg) = run_shell_mode(port) {
-            eprintln!("[ERROR] {msg}");
-            process::exit(1);
-        }
-        Ok(())
-    } else {
-        let options = eframe::NativeOptions {
-            viewport: ViewportBuilder::default()
-                .with_title(format!("{} [v{}]", PROGRAM_NAME, PROGRAM_VERSION))
-                .with_inner_size([WINDOW_SIZE.0, WINDOW_SIZE.1])
-                .with_resizable(true),
-            ..Default::default()
-        };
-        eframe::run_native(
-            "bride",
-            options,
-            Box::new(move |cc| {
-                egui_extras::install_image_loaders(&cc.egui_ctx);
-                cc.egui_ctx.set_visuals(egui::Visuals::dark());
-                Box::new(Screen::new(port, cc))
+    let r = match parse_command_line_args(env::args().collect()) {
+        Ok(options) => match options.mode {
+            Mode::Gui => {
+                let setup = command_queue(&options).and_then(|q| Ok((q, start_server(&options)?)));
+                match setup {
+                    Ok((queue, process)) => return run_gui_mode(queue, options.endpoint, process),
+                    Err(e) => Err(e),
+                }
+            }
+            Mode::Shell => command_queue(&options).and_then(|queue| {
+                let process = start_server(&options)?;
+                run_shell_mode(queue, &options, process)
+            }),
+            Mode::Mock => MockServer::new().run(&options.endpoint),
+            Mode::Benchmark(count) => command_queue(&options).and_then(|queue| {
+                //killed when it goes out of scope, once the benchmark is over
+                let _process = start_server(&options)?;
+                benchmark::run(queue, &options.endpoint, count)
             }),
-        )
+            Mode::ViewLog(ref path) => record::view(path),
+            Mode::Proxy(ref listen) => command_queue(&options)
+                .and_then(|queue| Proxy::new(queue, options.endpoint.clone()).run(listen)),
+        },
+        Err(msg) => Err(msg.into()),
+    };
+    if let Err(msg) = r {
+        eprintln!("[ERROR] {msg}");
+        process::exit(1);
     }
+    Ok(())
 }
 
-fn parse_command_line_args(args: Vec<String>) -> (bool, u16) {
+fn parse_command_line_args(args: Vec<String>) -> Result<Options, String> {
     let port = match args.iter().position(|x| x == "-p" || x == "--port") {
         Some(n) => {
             if let Some(port) = args.get(n+1) {
@@ -58,13 +104,182 @@ fn parse_command_line_args(args: Vec<String>) -> (bool, u16) {
         },
         None => DEFAULT_PORT
     };
-    let shell = args.iter().filter(|x| *x == "-s" || *x == "--shell").count() > 0;
-    (shell, port)
+    //a port given as part of the address wins over --port
+    let address = args.iter().position(|x| x == "-a" || x == "--address" || x == "--host");
+    let socket = args.iter().position(|x| x == "--socket");
+    let replay = args.iter().position(|x| x == "--replay");
+    let value = |n: usize| args.get(n + 1).ok_or(format!("Missing value for {}", args[n]));
+    let endpoint = match (address, socket, replay) {
+        (Some(n), None, None) => Endpoint::parse(value(n)?, port)?,
+        #[cfg(unix)]
+        (None, Some(n), None) => Endpoint::Unix(value(n)?.into()),
+        #[cfg(not(unix))]
+        (None, Some(_), None) => {
+            return Err(String::from("Unix sockets aren't supported on this platform"))
+        }
+        (None, None, Some(n)) => Endpoint::Replay(value(n)?.into()),
+        (None, None, None) => Endpoint::new(DEFAULT_HOST, port),
+        _ => return Err(String::from("Use only one of --address, --socket and --replay")),
+    };
+    let record = match args.iter().position(|x| x == "--record") {
+        Some(n) => Some(PathBuf::from(value(n)?)),
+        None => None,
+    };
+    let stats_csv = match args.iter().position(|x| x == "--stats-csv") {
+        Some(n) => Some(PathBuf::from(value(n)?)),
+        None => None,
+    };
+    let max_frame_size = match args.iter().position(|x| x == "--max-frame-size") {
+        Some(n) => match value(n)?.parse::<usize>() {
+            Ok(size) if size > 0 => Some(size),
+            _ => return Err(String::from("Invalid number of bytes for --max-frame-size")),
+        },
+        None => None,
+    };
+    let view_log = match args.iter().position(|x| x == "--view-log") {
+        Some(n) => Some(PathBuf::from(value(n)?)),
+        None => None,
+    };
+    let seconds = |name: &str| -> Result<Option<Duration>, String> {
+        match args.iter().position(|x| x == name) {
+            Some(n) => match value(n)?.parse::<f64>() {
+                Ok(s) if s.is_finite() && s > 0.0 => Ok(Some(Duration::from_secs_f64(s))),
+                _ => Err(format!("Invalid number of seconds for {name}")),
+            },
+            None => Ok(None),
+        }
+    };
+    let mut keepalive = KeepAlive {
+        heartbeat: seconds("--heartbeat")?,
+        ..Default::default()
+    };
+    if let Some(timeout) = seconds("--response-timeout")? {
+        keepalive.response_timeout = timeout;
+    }
+    let server_cmd = match args.iter().position(|x| x == "--server-cmd") {
+        Some(_) if matches!(endpoint, Endpoint::Replay(_)) => {
+            return Err(String::from("A replay has no server to start"))
+        }
+        Some(n) => Some(tokenize(value(n)?)),
+        None => None,
+    };
+    let restart_server = args.iter().any(|x| x == "--restart-server");
+    let script = match args.iter().position(|x| x == "--script") {
+        Some(n) => Some(PathBuf::from(value(n)?)),
+        None => None,
+    };
+    //every --exec adds one command, run in the order given
+    let exec = args
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| *x == "--exec")
+        .map(|(n, _)| value(n).cloned())
+        .collect::<Result<Vec<_>, _>>()?;
+    if script.is_some() && !exec.is_empty() {
+        return Err(String::from("Use only one of --script and --exec"));
+    }
+    let fail_fast = args.iter().any(|x| x == "--fail-fast");
+    let shell = args.iter().filter(|x| *x == "-s" || *x == "--shell").count() > 0
+        || script.is_some()
+        || !exec.is_empty();
+    let mock = args.iter().filter(|x| *x == "-m" || *x == "--mock").count() > 0;
+    let benchmark = args.iter().position(|x| x == "-b" || x == "--benchmark").map(|n| {
+        args.get(n + 1)
+            .and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_BENCHMARK_SIZE)
+    });
+    //the address to listen on is optional, like the benchmark's command count
+    let proxy = match args.iter().position(|x| x == "--proxy") {
+        Some(n) => match args.get(n + 1).filter(|x| !x.starts_with('-')) {
+            Some(address) => Some(Endpoint::parse(address, DEFAULT_PROXY_PORT)?),
+            None => Some(Endpoint::new(DEFAULT_HOST, DEFAULT_PROXY_PORT)),
+        },
+        None => None,
+    };
+    let mode = match (shell, mock, benchmark, view_log, proxy) {
+        (_, _, _, Some(path), _) => Mode::ViewLog(path),
+        (_, _, _, _, Some(listen)) => Mode::Proxy(listen),
+        (_, true, _, _, _) => Mode::Mock,
+        (_, _, Some(count), _, _) => Mode::Benchmark(count),
+        (true, _, _, _, _) => Mode::Shell,
+        _ => Mode::Gui,
+    };
+    Ok(Options {
+        mode,
+        endpoint,
+        record,
+        stats_csv,
+        max_frame_size,
+        keepalive,
+        server_cmd,
+        restart_server,
+        script,
+        exec,
+        fail_fast,
+    })
+}
+
+//starts the game if asked to, and waits until it can be connected to
+fn start_server(options: &Options) -> GenericResult<Option<ServerProcess>> {
+    let Some(command) = &options.server_cmd else { return Ok(None) };
+    let mut process = ServerProcess::spawn(command.clone(), options.restart_server)?;
+    println!("Waiting for the server to accept connections on {}...", options.endpoint);
+    process.wait_until_ready(&options.endpoint)?;
+    Ok(Some(process))
 }
 
-fn run_shell_mode(port: u16) -> UnitResult {
-    let mut shell = Shell::new(port);
-    shell.interactive_loop()?;
+fn command_queue(options: &Options) -> GenericResult<CommandQueue> {
+    let mut queue = CommandQueue::new();
+    queue.set_keepalive(options.keepalive);
+    if let Some(path) = &options.record {
+        queue.set_recorder(Recorder::create(path)?);
+    }
+    if let Some(path) = &options.stats_csv {
+        queue.set_stats_file(path.clone());
+    }
+    if let Some(size) = options.max_frame_size {
+        queue.set_max_frame_size(size);
+    }
+    Ok(queue)
+}
+
+fn run_shell_mode(
+    queue: CommandQueue, options: &Options, process: Option<ServerProcess>,
+) -> UnitResult {
+    let mut shell = Shell::new(queue, options.endpoint.clone(), process);
+    //piped input is a script too, there's nobody to prompt
+    let r = match &options.script {
+        _ if !options.exec.is_empty() => shell.run_commands(&options.exec, options.fail_fast),
+        Some(path) => {
+            let file = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            shell.run_script(BufReader::new(file).lines(), options.fail_fast)
+        }
+        None if !io::stdin().is_terminal() => {
+            shell.run_script(io::stdin().lock().lines(), options.fail_fast)
+        }
+        None => shell.interactive_loop(),
+    };
     shell.shutdown()?;
-    Ok(())
+    r
+}
+
+fn run_gui_mode(
+    queue: CommandQueue, endpoint: Endpoint, process: Option<ServerProcess>,
+) -> eframe::Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: ViewportBuilder::default()
+            .with_title(format!("{} [v{}]", PROGRAM_NAME, PROGRAM_VERSION))
+            .with_inner_size([WINDOW_SIZE.0, WINDOW_SIZE.1])
+            .with_resizable(true),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "bride",
+        options,
+        Box::new(move |cc| {
+            egui_extras::install_image_loaders(&cc.egui_ctx);
+            cc.egui_ctx.set_visuals(egui::Visuals::dark());
+            Box::new(Screen::new(queue, endpoint, process, cc))
+        }),
+    )
 }
@@ -0,0 +1,238 @@
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{fs::FileTypeExt, net::UnixListener},
+};
+use std::{io, net::TcpListener, thread, time::Duration};
+
+use self::track::{Reply, Track};
+use crate::{
+    server::{
+        self, Endpoint, FrameReader, ServerEvent, Transport, EVENT_SIGNAL, PROMPT_MESSAGE,
+    },
+    utils::UnitResult,
+};
+
+mod preview;
+mod track;
+
+const PLAY_TEST_DURATION: Duration = Duration::from_secs(2);
+//commands are short, anything this big is a broken client
+const MAX_COMMAND_SIZE: usize = 64 * 1024;
+
+//an in-memory stand-in for the game, speaking the same protocol on the same port
+pub struct MockServer {
+    track: Track,
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        MockServer {
+            track: Track::new(),
+        }
+    }
+
+    pub fn run(&mut self, endpoint: &Endpoint) -> UnitResult {
+        match endpoint {
+            Endpoint::Tcp { .. } => {
+                let listener = TcpListener::bind(&endpoint.socket_addrs()?[..])?;
+                println!("Mock server listening on {}", listener.local_addr()?);
+                for stream in listener.incoming() {
+                    let stream = stream?;
+                    //responses and prompts are separate writes, don't let them wait on each other
+                    stream.set_nodelay(true)?;
+                    let peer = stream.peer_addr()?.to_string();
+                    self.session(stream, &peer);
+                }
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                //a socket left behind by a previous run would make bind() fail
+                if fs::metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                println!("Mock server listening on {}", path.display());
+                for (i, stream) in listener.incoming().enumerate() {
+                    self.session(stream?, &format!("#{}", i + 1));
+                }
+            }
+            Endpoint::Replay(_) => return Err("The mock server can't listen on a replay.".into()),
+        }
+        Ok(())
+    }
+
+    fn session<T: Transport>(&mut self, stream: T, peer: &str) {
+        println!("Client connected: {peer}");
+        //the game only talks to one client at a time, so neither do we
+        match self.serve(stream) {
+            Ok(()) => println!("Client disconnected: {peer}"),
+            Err(e) => println!("Client dropped ({peer}): {e}"),
+        }
+    }
+
+    fn serve<T: Transport>(&mut self, mut stream: T) -> UnitResult {
+        write_message(&mut stream, "<READY>")?;
+        write_message(&mut stream, PROMPT_MESSAGE)?;
+        let mut reader = FrameReader::new(stream.try_clone()?, MAX_COMMAND_SIZE);
+        while let Some(frame) = reader.read_frame()? {
+            let line = match frame {
+                Ok(line) => line,
+                Err(e) => {
+                    println!("Malformed message: {e}");
+                    continue;
+                }
+            };
+            let line = line.trim();
+            match self.track.execute(line) {
+                Reply::Ok(body) => write_message(&mut stream, &format_response(line, "<OK>", &body))?,
+                Reply::Error(body) => {
+                    write_message(&mut stream, &format_response(line, "<ERROR>", &body))?
+                }
+                Reply::PlayTest => {
+                    write_message(&mut stream, &format_response(line, "<OK>", ""))?;
+                    write_message(&mut stream, "<PAUSE>")?;
+                    thread::sleep(PLAY_TEST_DURATION);
+                    //back at the prompt first, so the editor can react right away
+                    write_message(&mut stream, PROMPT_MESSAGE)?;
+                    let event = format!("{EVENT_SIGNAL} {}", ServerEvent::PlayTestFinished);
+                    write_message(&mut stream, &event)?;
+                    continue;
+                }
+                Reply::Exit => {
+                    write_message(&mut stream, "<EXIT>")?;
+                    return Ok(());
+                }
+            }
+            write_message(&mut stream, PROMPT_MESSAGE)?;
+        }
+        Ok(())
+    }
+}
+
+fn format_response(command: &str, signal: &str, body: &str) -> String {
+    if body.is_empty() {
+        format!("{} :: {}", command, signal)
+    } else {
+        format!("{} :: {}\n{}", command, signal, body)
+    }
+}
+
+fn write_message(stream: &mut impl io::Write, msg: &str) -> io::Result<()> {
+    stream.write_all(&server::encode_message(msg))?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Instant};
+
+    use super::*;
+    use crate::server::{
+        commands::TrackCommand,
+        parser::{FromResponse, SectionRecord},
+        CallError, CommandQueue,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    //a mock serving a single client on a free port, and a queue connected to it
+    fn connect() -> CommandQueue {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            MockServer::new().session(stream, "test");
+        });
+        let mut queue = CommandQueue::new();
+        queue.connect(&Endpoint::new("127.0.0.1", port)).unwrap();
+        queue
+    }
+
+    fn sections(queue: &mut CommandQueue) -> Vec<SectionRecord> {
+        let response = queue.call(&TrackCommand::SectionList.to_string(), TIMEOUT).unwrap();
+        Vec::<SectionRecord>::from_response(&response).unwrap()
+    }
+
+    #[test]
+    fn edits_through_the_queue() {
+        let mut queue = connect();
+        assert_eq!(sections(&mut queue).len(), 1);
+        let add = TrackCommand::SectionAdd { index: 1 };
+        queue.call(&add.to_string(), TIMEOUT).unwrap();
+        let set = TrackCommand::SectionSet {
+            index: 1,
+            length: 300,
+            curve: -2,
+            slope: 1,
+            split: 0,
+        };
+        queue.call(&set.to_string(), TIMEOUT).unwrap();
+        let s = sections(&mut queue);
+        assert_eq!((s.len(), s[1].length, s[1].curve, s[1].slope), (2, 300, -2, 1));
+        queue.call(&TrackCommand::Undo.to_string(), TIMEOUT).unwrap();
+        assert_eq!(sections(&mut queue)[1].length, 250);
+        //only what was asked for shows up, not the handshake
+        let measured = queue.metrics().commands().map(|(id, s)| (id, s.count)).collect::<Vec<_>>();
+        assert_eq!(
+            measured,
+            vec![("section-add", 1), ("section-list", 3), ("section-set", 1), ("undo", 1)]
+        );
+        queue.disconnect().unwrap();
+    }
+
+    #[test]
+    fn errors_and_strings_through_the_queue() {
+        let mut queue = connect();
+        let bad = TrackCommand::SectionDelete { index: 9 };
+        match queue.call(&bad.to_string(), TIMEOUT) {
+            Err(CallError::Server(r)) => assert_eq!(r.result(), "Invalid section index: 9"),
+            r => panic!("expected a server error, got {r:?}"),
+        }
+        let name = TrackCommand::HeaderNameSet {
+            name: String::from("Say \"hi\" \\ bye"),
+        };
+        queue.call(&name.to_string(), TIMEOUT).unwrap();
+        let header = queue.call(&TrackCommand::HeaderGet.to_string(), TIMEOUT).unwrap();
+        assert!(header.result().lines().any(|l| l == "name Say \"hi\" \\ bye"));
+        queue.disconnect().unwrap();
+    }
+
+    #[test]
+    fn merged_requests_share_one_response() {
+        let mut queue = connect();
+        let list = TrackCommand::SectionList.to_string();
+        let tickets = vec![queue.send(&list), queue.send(&list)];
+        let deadline = Instant::now() + TIMEOUT;
+        let received = loop {
+            queue.wait(TIMEOUT).unwrap();
+            if let Some(r) = queue.receive() {
+                break r;
+            }
+            assert!(Instant::now() < deadline, "no response to the merged requests");
+        };
+        assert_eq!(received.0, tickets);
+        assert!(queue.receive().is_none());
+        queue.disconnect().unwrap();
+    }
+
+    #[test]
+    fn a_batch_is_undone_in_one_step() {
+        let mut queue = connect();
+        assert_eq!(sections(&mut queue).len(), 1);
+        queue.begin_batch();
+        for index in 1..=3 {
+            queue.call(&TrackCommand::SectionAdd { index }.to_string(), TIMEOUT).unwrap();
+        }
+        queue.commit_batch();
+        assert_eq!(sections(&mut queue).len(), 4);
+        let ticket = queue.undo();
+        let deadline = Instant::now() + TIMEOUT;
+        while queue.poll(ticket).is_none() {
+            assert!(Instant::now() < deadline, "no response to undo");
+            queue.wait(TIMEOUT).unwrap();
+        }
+        assert_eq!(sections(&mut queue).len(), 1);
+        queue.disconnect().unwrap();
+    }
+}
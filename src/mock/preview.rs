@@ -0,0 +1,64 @@
+use std::io::Cursor;
+
+use base64::Engine;
+use image::{ImageOutputFormat, Rgb, RgbImage};
+
+use super::track::{Project, View};
+
+const DEPTH_SCALE: f32 = 20.0;
+const STRIPE_LENGTH: i64 = 40;
+const ROAD_WIDTH: f32 = 0.4;
+const CURVE_SCALE: f32 = 0.6;
+
+//draws a crude pseudo-3d view of the track, encoded the way view-preview sends it
+pub fn render(project: &Project, view: &View, size: (u32, u32)) -> Result<String, String> {
+    let (w, h) = size;
+    let color = |i: usize| {
+        let [r, g, b, _] = project.colors[i].to_be_bytes();
+        Rgb([r, g, b])
+    };
+    let horizon = if view.overview { h / 4 } else { h / 2 };
+    //lengths and positions are i32, but their sums may not fit one
+    let track_length = project.sections.iter().map(|s| s.length as i64).sum::<i64>();
+    let mut image = RgbImage::from_pixel(w, h, color(7));
+    for y in (horizon + 1)..h {
+        let depth = (h - horizon) as f32 / (y - horizon) as f32;
+        let z = view.z as i64 + (depth * DEPTH_SCALE) as i64;
+        let stripe = (z / STRIPE_LENGTH % 2) as usize;
+        let scale = 1.0 / depth;
+        let (curve, lanes) = locate(project, z);
+        let bend = curve as f32 * (depth - 1.0).powi(2) * CURVE_SCALE;
+        let center = w as f32 / 2.0 + (bend - view.x as f32) * scale;
+        let half = w as f32 * ROAD_WIDTH * scale * (1.0 + 0.15 * lanes as f32);
+        for x in 0..w {
+            let d = (x as f32 - center).abs();
+            let pixel = if z > track_length || d > half {
+                color(stripe)
+            } else if d > half * 0.92 {
+                color(4 + stripe)
+            } else if stripe == 0 && d < half * 0.02 {
+                color(6)
+            } else {
+                color(2 + stripe)
+            };
+            let x = if view.reverse { w - 1 - x } else { x };
+            image.put_pixel(x, y, pixel);
+        }
+    }
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+        .map_err(|e| e.to_string())?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+//curve and lane count of the section found at a given distance
+fn locate(project: &Project, mut z: i64) -> (i32, i32) {
+    for s in &project.sections {
+        if z < s.length as i64 {
+            return (s.curve, s.split.min(3));
+        }
+        z -= s.length as i64;
+    }
+    (0, 0)
+}
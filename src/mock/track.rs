@@ -0,0 +1,713 @@
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::preview;
+use crate::{
+    server::{self, commands::COMMANDS, parser::EMPTY_SIGNAL, BATCH_BEGIN, BATCH_END},
+    utils,
+};
+
+const DEFAULT_SECTION_LENGTH: i32 = 250;
+const DEFAULT_PREVIEW_SIZE: (u32, u32) = (640, 360);
+const MAX_PREVIEW_SIZE: u32 = 4096;
+
+pub enum Reply {
+    Ok(String),
+    Error(String),
+    PlayTest,
+    Exit,
+}
+
+struct Package {
+    name: &'static str,
+    backgrounds: &'static [&'static str],
+    textures: &'static [&'static str],
+    props: &'static [&'static str],
+}
+
+static PACKAGES: [Package; 3] = [
+    Package {
+        name: "coast",
+        backgrounds: &["sunset", "harbor", "cliffs"],
+        textures: &["asphalt", "cobblestone"],
+        props: &["palm", "lamp-post", "billboard", "rock"],
+    },
+    Package {
+        name: "canyon",
+        backgrounds: &["mesa", "dusk"],
+        textures: &["dirt", "asphalt"],
+        props: &["cactus", "boulder", "sign", "arch"],
+    },
+    Package {
+        name: "city",
+        backgrounds: &["skyline", "night", "rain"],
+        textures: &["asphalt", "concrete", "bricks"],
+        props: &["building", "lamp-post", "billboard", "tree", "barrier"],
+    },
+];
+
+#[derive(Clone)]
+pub struct Section {
+    pub length: i32,
+    pub curve: i32,
+    pub slope: i32,
+    pub split: i32,
+    pub patterns: Vec<Pattern>,
+}
+
+#[derive(Clone)]
+pub struct Pattern {
+    prop: String,
+    position: i32,
+    size: i32,
+    spacing: i32,
+    x: i32,
+    freq: i32,
+    amp: i32,
+    offset: i32,
+    flags: u32,
+}
+
+#[derive(Clone)]
+struct Header {
+    package: String,
+    name: String,
+    background: String,
+    texture: String,
+    flags: u32,
+    random_seed: i64,
+}
+
+#[derive(Clone)]
+pub struct Project {
+    header: Header,
+    pub sections: Vec<Section>,
+    pub colors: [u32; 8],
+}
+
+#[derive(Default)]
+pub struct View {
+    pub x: i32,
+    pub z: i32,
+    pub overview: bool,
+    pub reverse: bool,
+}
+
+pub struct Track {
+    project: Project,
+    undo: Vec<Project>,
+    redo: Vec<Project>,
+    //how much undo history there was when the open batch began
+    batch: Option<usize>,
+    saved: BTreeMap<String, Project>,
+    file_name: Option<String>,
+    dirty: bool,
+    view: View,
+    preview_size: (u32, u32),
+    rng: u64,
+}
+
+impl Section {
+    fn new(length: i32, curve: i32, slope: i32, split: i32) -> Self {
+        Section {
+            length,
+            curve,
+            slope,
+            split,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl Project {
+    fn new(package: &Package) -> Self {
+        Project {
+            header: Header {
+                package: String::from(package.name),
+                name: String::from("Untitled"),
+                background: String::from(package.backgrounds[0]),
+                texture: String::from(package.textures[0]),
+                flags: 0,
+                random_seed: 0,
+            },
+            sections: vec![Section::new(DEFAULT_SECTION_LENGTH, 0, 0, 0)],
+            colors: [
+                0x3a7d44ff, 0x2f6b3aff, 0x55555aff, 0x4c4c50ff, 0xd8d8d8ff, 0xb02020ff,
+                0xf0f0f0ff, 0x9fb8d0ff,
+            ],
+        }
+    }
+
+    fn package(&self) -> &'static Package {
+        PACKAGES
+            .iter()
+            .find(|p| p.name == self.header.package)
+            .unwrap_or(&PACKAGES[0])
+    }
+
+    fn section(&mut self, index: usize) -> Result<&mut Section, String> {
+        self.sections
+            .get_mut(index)
+            .ok_or(format!("Invalid section index: {index}"))
+    }
+
+    fn pattern(&mut self, section: usize, index: usize) -> Result<&mut Pattern, String> {
+        self.section(section)?
+            .patterns
+            .get_mut(index)
+            .ok_or(format!("Invalid pattern index: {index}"))
+    }
+}
+
+impl Track {
+    pub fn new() -> Self {
+        let mut saved = BTreeMap::new();
+        let mut canyon = Project::new(&PACKAGES[1]);
+        canyon.header.name = String::from("Canyon Run");
+        canyon.sections = vec![
+            Section::new(400, 0, 0, 0),
+            Section::new(300, 3, 1, 0),
+            Section::new(250, -2, -1, 1),
+            Section::new(500, 0, 0, 2),
+        ];
+        saved.insert(String::from("canyon"), canyon);
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x2545F4914F6CDD1D);
+        Track {
+            project: Project::new(&PACKAGES[0]),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            batch: None,
+            saved,
+            file_name: None,
+            dirty: false,
+            view: Default::default(),
+            preview_size: DEFAULT_PREVIEW_SIZE,
+            rng: seed | 1,
+        }
+    }
+
+    pub fn execute(&mut self, line: &str) -> Reply {
+        let parts = server::tokenize(line);
+        let (cmd, args) = match parts.split_first() {
+            Some((cmd, args)) => (cmd.as_str(), args),
+            None => return Reply::Error(String::from("Empty command.")),
+        };
+        let result = match cmd {
+            "track-play-test" => return Reply::PlayTest,
+            "exit" | "quit" => return Reply::Exit,
+            "undo" => self.undo(),
+            "redo" => self.redo(),
+            BATCH_BEGIN => {
+                self.batch.get_or_insert(self.undo.len());
+                Ok(String::new())
+            }
+            BATCH_END => {
+                //everything since the batch began becomes a single undo step
+                if let Some(start) = self.batch.take() {
+                    self.undo.truncate(start + 1);
+                }
+                Ok(String::new())
+            }
+            "protocol-version" => Ok(server::PROTOCOL_VERSION.to_string()),
+            "protocol-commands" => {
+                let commands = COMMANDS.iter().map(|(c, _)| *c);
+                Ok(commands.chain([BATCH_BEGIN, BATCH_END]).collect::<Vec<_>>().join("\n"))
+            }
+            x if x.starts_with("section-") => self.section_command(x, args),
+            x if x.starts_with("pattern-") => self.pattern_command(x, args),
+            x if x.starts_with("header-") => self.header_command(x, args),
+            x if x.starts_with("color-") => self.color_command(x, args),
+            x if x.starts_with("project-") => self.project_command(x, args),
+            x if x.starts_with("package-") => self.package_command(x, args),
+            x if x.starts_with("view-") || x.starts_with("track-") => self.view_command(x, args),
+            _ => Err(format!("Unknown command: {cmd}")),
+        };
+        match result {
+            Ok(body) => Reply::Ok(body),
+            Err(body) => Reply::Error(body),
+        }
+    }
+
+    //runs a mutating command against a snapshot, so failures leave no trace
+    fn modify(
+        &mut self, f: impl FnOnce(&mut Project) -> Result<String, String>,
+    ) -> Result<String, String> {
+        let mut project = self.project.clone();
+        let r = f(&mut project)?;
+        self.undo.push(std::mem::replace(&mut self.project, project));
+        self.redo.clear();
+        self.dirty = true;
+        Ok(r)
+    }
+
+    fn undo(&mut self) -> Result<String, String> {
+        let prev = self.undo.pop().ok_or("Nothing to undo.")?;
+        self.redo.push(std::mem::replace(&mut self.project, prev));
+        self.dirty = true;
+        Ok(String::new())
+    }
+
+    fn redo(&mut self) -> Result<String, String> {
+        let next = self.redo.pop().ok_or("Nothing to redo.")?;
+        self.undo.push(std::mem::replace(&mut self.project, next));
+        self.dirty = true;
+        Ok(String::new())
+    }
+
+    fn section_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        match cmd {
+            "section-list" => Ok(list_or_empty(
+                self.project.sections.iter().enumerate().map(|(i, s)| {
+                    format!("section {} {} {} {} {}", i, s.length, s.curve, s.slope, s.split)
+                }),
+            )),
+            "section-metrics" => {
+                //any i32 is a valid length, so sums are done in i64 to stay in range
+                let sections = &self.project.sections;
+                let sum = |f: fn(&Section) -> i32| {
+                    sections.iter().map(|s| f(s) as i64).sum::<i64>()
+                };
+                let mut view_z = self.view.z as i64;
+                let mut view = 0;
+                for s in sections {
+                    if view_z - s.length as i64 <= 0 {
+                        break;
+                    }
+                    view_z -= s.length as i64;
+                    view += 1;
+                }
+                Ok(format!(
+                    "view {}\nsegments {}\ncurve {}\nslope {}",
+                    view.min(sections.len().saturating_sub(1)),
+                    sum(|s| s.length),
+                    sum(|s| s.curve),
+                    sum(|s| s.slope)
+                ))
+            }
+            "section-add" => {
+                let index = argument::<usize>(args, 0)?;
+                self.modify(|p| {
+                    let index = index.min(p.sections.len());
+                    p.sections
+                        .insert(index, Section::new(DEFAULT_SECTION_LENGTH, 0, 0, 0));
+                    Ok(String::new())
+                })
+            }
+            "section-delete" => {
+                let index = argument::<usize>(args, 0)?;
+                self.modify(|p| {
+                    p.section(index)?;
+                    p.sections.remove(index);
+                    Ok(String::new())
+                })
+            }
+            "section-duplicate" => {
+                let index = argument::<usize>(args, 0)?;
+                self.modify(|p| {
+                    let copy = p.section(index)?.clone();
+                    p.sections.insert(index + 1, copy);
+                    Ok(String::new())
+                })
+            }
+            "section-move" => {
+                let (index, delta) = (argument::<usize>(args, 0)?, argument::<isize>(args, 1)?);
+                self.modify(|p| {
+                    let target = index
+                        .checked_add_signed(delta)
+                        .filter(|t| *t < p.sections.len())
+                        .ok_or("Cannot move section outside of the track.")?;
+                    p.section(index)?;
+                    p.sections.swap(index, target);
+                    Ok(String::new())
+                })
+            }
+            "section-set" => {
+                let index = argument::<usize>(args, 0)?;
+                let (length, curve, slope, split) = (
+                    argument::<i32>(args, 1)?,
+                    argument::<i32>(args, 2)?,
+                    argument::<i32>(args, 3)?,
+                    argument::<i32>(args, 4)?,
+                );
+                self.modify(|p| {
+                    let s = p.section(index)?;
+                    (s.length, s.curve, s.slope, s.split) = (length, curve, slope, split);
+                    Ok(String::new())
+                })
+            }
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    fn pattern_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        let section = argument::<usize>(args, 0)?;
+        match cmd {
+            "pattern-list" => {
+                let s = self
+                    .project
+                    .sections
+                    .get(section)
+                    .ok_or(format!("Invalid section index: {section}"))?;
+                Ok(list_or_empty(s.patterns.iter().enumerate().map(|(i, p)| {
+                    format!(
+                        "pattern {} {} {} {} {} {} {} {} {} {} {}",
+                        section,
+                        i,
+                        p.prop,
+                        p.position,
+                        p.size,
+                        p.spacing,
+                        p.x,
+                        p.freq,
+                        p.amp,
+                        p.offset,
+                        p.flags
+                    )
+                })))
+            }
+            "pattern-add" => self.modify(|p| {
+                let prop = String::from(p.package().props[0]);
+                p.section(section)?.patterns.push(Pattern {
+                    prop,
+                    position: 0,
+                    size: 10,
+                    spacing: 5,
+                    x: 0,
+                    freq: 1,
+                    amp: 0,
+                    offset: 0,
+                    flags: 0,
+                });
+                Ok(String::new())
+            }),
+            "pattern-delete" => {
+                let index = argument::<usize>(args, 1)?;
+                self.modify(|p| {
+                    p.pattern(section, index)?;
+                    p.section(section)?.patterns.remove(index);
+                    Ok(String::new())
+                })
+            }
+            "pattern-duplicate" => {
+                let index = argument::<usize>(args, 1)?;
+                self.modify(|p| {
+                    let copy = p.pattern(section, index)?.clone();
+                    p.section(section)?.patterns.insert(index + 1, copy);
+                    Ok(String::new())
+                })
+            }
+            "pattern-adjust" => {
+                let index = argument::<usize>(args, 1)?;
+                self.modify(|p| {
+                    let length = p.section(section)?.length;
+                    let pattern = p.pattern(section, index)?;
+                    pattern.position = pattern.position.clamp(0, length.saturating_sub(1).max(0));
+                    let room = (length - pattern.position) / pattern.spacing.max(1);
+                    pattern.size = pattern.size.clamp(1, room.max(1));
+                    Ok(String::new())
+                })
+            }
+            "pattern-copy-all" => {
+                let source = argument::<usize>(args, 1)?;
+                self.modify(|p| {
+                    let patterns = p.section(source)?.patterns.clone();
+                    p.section(section)?.patterns = patterns;
+                    Ok(String::new())
+                })
+            }
+            "pattern-set" => {
+                let index = argument::<usize>(args, 1)?;
+                let prop = argument::<String>(args, 2)?;
+                let values = (3..10)
+                    .map(|i| argument::<i32>(args, i))
+                    .collect::<Result<Vec<i32>, String>>()?;
+                let flags = argument::<u32>(args, 10)?;
+                self.modify(|p| {
+                    if !p.package().props.contains(&prop.as_str()) {
+                        return Err(format!("Unknown prop: {prop}"));
+                    }
+                    *p.pattern(section, index)? = Pattern {
+                        prop,
+                        position: values[0],
+                        size: values[1],
+                        spacing: values[2],
+                        x: values[3],
+                        freq: values[4],
+                        amp: values[5],
+                        offset: values[6],
+                        flags,
+                    };
+                    Ok(String::new())
+                })
+            }
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    fn header_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        match cmd {
+            "header-get" => {
+                let h = &self.project.header;
+                Ok(format!(
+                    "package {}\nname {}\nbackground {}\ntexture {}\nrandom-seed {}\nflags {}",
+                    h.package, h.name, h.background, h.texture, h.random_seed, h.flags
+                ))
+            }
+            "header-name-set" => {
+                let name = argument::<String>(args, 0)?;
+                self.modify(|p| {
+                    p.header.name = name;
+                    Ok(String::new())
+                })
+            }
+            "header-background-set" => {
+                let background = argument::<String>(args, 0)?;
+                self.modify(|p| {
+                    if !p.package().backgrounds.contains(&background.as_str()) {
+                        return Err(format!("Unknown background: {background}"));
+                    }
+                    p.header.background = background;
+                    Ok(String::new())
+                })
+            }
+            "header-texture-set" => {
+                let texture = argument::<String>(args, 0)?;
+                self.modify(|p| {
+                    if !p.package().textures.contains(&texture.as_str()) {
+                        return Err(format!("Unknown texture: {texture}"));
+                    }
+                    p.header.texture = texture;
+                    Ok(String::new())
+                })
+            }
+            "header-flags-set" => {
+                let raw = argument::<String>(args, 0)?;
+                let flags = match raw.strip_prefix("0b") {
+                    Some(bits) => u32::from_str_radix(bits, 2),
+                    None => raw.parse::<u32>(),
+                }
+                .map_err(|_| format!("Invalid flags: {raw}"))?;
+                self.modify(|p| {
+                    p.header.flags = flags;
+                    Ok(String::new())
+                })
+            }
+            "header-new-random-seed" => {
+                let seed = self.next_random() as i64;
+                self.modify(|p| {
+                    p.header.random_seed = seed;
+                    Ok(String::new())
+                })
+            }
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    fn color_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        match cmd {
+            "color-list" => Ok(self
+                .project
+                .colors
+                .iter()
+                .map(|c| format!("{:08x}", c))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "color-set" => {
+                let index = argument::<usize>(args, 0)?;
+                let raw = argument::<String>(args, 1)?;
+                let color = u32::from_str_radix(raw.trim_start_matches("0x"), 16)
+                    .map_err(|_| format!("Invalid color: {raw}"))?;
+                self.modify(|p| {
+                    *p.colors
+                        .get_mut(index)
+                        .ok_or(format!("Invalid color index: {index}"))? = color;
+                    Ok(String::new())
+                })
+            }
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    fn project_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        match cmd {
+            "project-list" => Ok(list_or_empty(self.saved.keys().cloned())),
+            "project-file-name" => Ok(self
+                .file_name
+                .clone()
+                .unwrap_or_else(|| String::from(EMPTY_SIGNAL))),
+            "project-new" => {
+                if !self.confirm_discard(boolean_argument(args, 0)?)? {
+                    return Ok(String::from("Cancelled."));
+                }
+                self.replace_project(Project::new(&PACKAGES[0]), None);
+                Ok(String::new())
+            }
+            "project-load" => {
+                let name = argument::<String>(args, 0)?;
+                let project = self
+                    .saved
+                    .get(&name)
+                    .cloned()
+                    .ok_or(format!("No project named '{name}'."))?;
+                if !self.confirm_discard(boolean_argument(args, 1)?)? {
+                    return Ok(String::from("Cancelled."));
+                }
+                self.replace_project(project, Some(name));
+                Ok(String::new())
+            }
+            "project-save" => {
+                let name = match args.first() {
+                    Some(name) => {
+                        let overwrite = boolean_argument(args, 1)?;
+                        let exists = self.saved.contains_key(name)
+                            && self.file_name.as_ref() != Some(name);
+                        match overwrite {
+                            None if exists => {
+                                return Err(format!("A project named '{name}' already exists."))
+                            }
+                            Some(false) => return Ok(String::from("Cancelled.")),
+                            _ => name.clone(),
+                        }
+                    }
+                    None => self
+                        .file_name
+                        .clone()
+                        .ok_or("This project has no file name yet.")?,
+                };
+                self.saved.insert(name.clone(), self.project.clone());
+                self.file_name = Some(name);
+                self.dirty = false;
+                Ok(String::new())
+            }
+            "project-delete" => {
+                let name = argument::<String>(args, 0)?;
+                self.saved
+                    .remove(&name)
+                    .ok_or(format!("No project named '{name}'."))?;
+                if self.file_name.as_ref() == Some(&name) {
+                    self.file_name = None;
+                }
+                Ok(String::new())
+            }
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    fn package_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        let package = self.project.package();
+        let list = |items: &[&str]| list_or_empty(items.iter().map(|x| String::from(*x)));
+        match cmd {
+            "package-list" => Ok(list_or_empty(PACKAGES.iter().map(|p| String::from(p.name)))),
+            "package-backgrounds" => Ok(list(package.backgrounds)),
+            "package-textures" => Ok(list(package.textures)),
+            "package-props" => Ok(list(package.props)),
+            "package-load" => {
+                let name = argument::<String>(args, 0)?;
+                let package = PACKAGES
+                    .iter()
+                    .find(|p| p.name == name)
+                    .ok_or(format!("No package named '{name}'."))?;
+                self.modify(|p| {
+                    p.header.package = String::from(package.name);
+                    p.header.background = String::from(package.backgrounds[0]);
+                    p.header.texture = String::from(package.textures[0]);
+                    Ok(String::new())
+                })
+            }
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    fn view_command(&mut self, cmd: &str, args: &[String]) -> Result<String, String> {
+        match cmd {
+            "view-preview" => preview::render(&self.project, &self.view, self.preview_size),
+            "view-preview-size" => {
+                let (w, h) = (argument::<u32>(args, 0)?, argument::<u32>(args, 1)?);
+                if w == 0 || h == 0 || w > MAX_PREVIEW_SIZE || h > MAX_PREVIEW_SIZE {
+                    return Err(format!("Invalid preview size: {w}x{h}"));
+                }
+                self.preview_size = (w, h);
+                Ok(String::new())
+            }
+            "view-position" => {
+                self.view.x = argument::<i32>(args, 0)?;
+                self.view.z = argument::<i32>(args, 1)?.max(0);
+                Ok(String::new())
+            }
+            "view-overview" => {
+                self.view.overview = boolean_argument(args, 0)?.unwrap_or(!self.view.overview);
+                Ok(String::new())
+            }
+            "track-reverse" => {
+                self.view.reverse = boolean_argument(args, 0)?.unwrap_or(!self.view.reverse);
+                Ok(String::new())
+            }
+            "view-state-info" => Ok(format!(
+                "position {} {}\nreverse {}\noverview {}",
+                self.view.x,
+                self.view.z,
+                utils::bool_string(self.view.reverse),
+                utils::bool_string(self.view.overview)
+            )),
+            _ => Err(format!("Unknown command: {cmd}")),
+        }
+    }
+
+    //Ok(true) means go ahead, Ok(false) means the client declined
+    fn confirm_discard(&self, confirmed: Option<bool>) -> Result<bool, String> {
+        match confirmed {
+            Some(c) => Ok(c),
+            None if self.dirty => Err(String::from("The current project has unsaved changes.")),
+            None => Ok(true),
+        }
+    }
+
+    fn replace_project(&mut self, project: Project, file_name: Option<String>) {
+        self.project = project;
+        self.file_name = file_name;
+        self.undo.clear();
+        self.redo.clear();
+        self.batch = None;
+        self.dirty = false;
+    }
+
+    fn next_random(&mut self) -> u64 {
+        //xorshift64, good enough for a fake random seed
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+fn list_or_empty(items: impl Iterator<Item = String>) -> String {
+    let list = items.collect::<Vec<_>>();
+    if list.is_empty() {
+        String::from(EMPTY_SIGNAL)
+    } else {
+        list.join("\n")
+    }
+}
+
+fn argument<T: FromStr>(args: &[String], index: usize) -> Result<T, String> {
+    let arg = args
+        .get(index)
+        .ok_or(format!("Missing argument #{}.", index + 1))?;
+    arg.parse::<T>()
+        .map_err(|_| format!("Invalid argument #{}: {}", index + 1, arg))
+}
+
+fn boolean_argument(args: &[String], index: usize) -> Result<Option<bool>, String> {
+    match args.get(index).map(String::as_str) {
+        None => Ok(None),
+        Some("#t") => Ok(Some(true)),
+        Some("#f") => Ok(Some(false)),
+        Some(x) => Err(format!("Invalid argument #{}: {}", index + 1, x)),
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    server::{
+        commands, encode_message, CommandQueue, Endpoint, FrameReader, ReconnectPolicy, Response,
+        ServerEvent, Ticket, EVENT_SIGNAL, PROMPT_MESSAGE,
+    },
+    utils::UnitResult,
+};
+
+//commands are short, anything this big is a broken client
+const MAX_COMMAND_SIZE: usize = 64 * 1024;
+//how often the loop wakes up on its own, for reconnects and keepalives
+const TICK_TIME: Duration = Duration::from_millis(100);
+//messages a client may fall behind by before it's dropped, so one stalled
+//editor can't hold up the others
+const CLIENT_BACKLOG: usize = 1024;
+
+enum ClientMessage {
+    Connected(usize, TcpStream),
+    Command(usize, String),
+    Disconnected(usize),
+    //the upstream connection has something for us
+    Wake,
+}
+
+struct Client {
+    stream: TcpStream,
+    writer: SyncSender<String>,
+    peer: String,
+}
+
+impl Client {
+    //every client is written to from its own thread, fed through a channel
+    fn new(stream: TcpStream, peer: String) -> io::Result<Self> {
+        let mut output = stream.try_clone()?;
+        let (writer, messages) = mpsc::sync_channel::<String>(CLIENT_BACKLOG);
+        thread::spawn(move || {
+            for msg in messages {
+                if write_message(&mut output, &msg).is_err() {
+                    //wakes up the reader, which reports the client gone
+                    let _ = output.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+        Ok(Client {
+            stream,
+            writer,
+            peer,
+        })
+    }
+}
+
+//sits between the game and any number of editors, so they can share the one
+//connection the game accepts; to each editor it looks like the game itself
+pub struct Proxy {
+    upstream: Endpoint,
+    queue: CommandQueue,
+    reconnect: ReconnectPolicy,
+    clients: HashMap<usize, Client>,
+    owners: HashMap<Ticket, usize>,
+    //kept apart from the owners, as other clients hear about changes even
+    //after the one that made them has gone
+    lines: HashMap<Ticket, String>,
+    paused: bool,
+}
+
+impl Proxy {
+    pub fn new(queue: CommandQueue, upstream: Endpoint) -> Self {
+        Proxy {
+            upstream,
+            queue,
+            reconnect: ReconnectPolicy::default(),
+            clients: HashMap::new(),
+            owners: HashMap::new(),
+            lines: HashMap::new(),
+            paused: false,
+        }
+    }
+
+    pub fn run(&mut self, listen: &Endpoint) -> UnitResult {
+        let listener = TcpListener::bind(&listen.socket_addrs()?[..])?;
+        println!("Proxy listening on {}, forwarding to {}", listener.local_addr()?, self.upstream);
+        let (sender, receiver) = mpsc::channel();
+        let waker = sender.clone();
+        self.queue.set_waker(move || {
+            let _ = waker.send(ClientMessage::Wake);
+        });
+        thread::spawn(move || accept(listener, sender));
+        self.serve(receiver)
+    }
+
+    fn serve(&mut self, receiver: Receiver<ClientMessage>) -> UnitResult {
+        loop {
+            match receiver.recv_timeout(TICK_TIME) {
+                Ok(msg) => self.handle(msg),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err("The listener stopped.".into()),
+            }
+            if !self.queue.connected() {
+                self.try_connect();
+                continue;
+            }
+            if let Err(e) = self.queue.update() {
+                println!("[ERROR] {e}");
+                self.drop_upstream();
+                continue;
+            }
+            while let Some(msg) = self.queue.diagnostic() {
+                println!("[WARNING] {msg}");
+            }
+            self.route_responses();
+            while let Some(event) = self.queue.event() {
+                self.broadcast(&[], &format!("{EVENT_SIGNAL} {event}"));
+            }
+            if self.queue.paused() != self.paused {
+                self.paused = self.queue.paused();
+                let msg = if self.paused { "<PAUSE>" } else { PROMPT_MESSAGE };
+                self.broadcast(&[], msg);
+            }
+            if self.queue.finished() {
+                println!("[OK] The server exited, waiting for it to come back.");
+                self.drop_upstream();
+            }
+        }
+    }
+
+    fn handle(&mut self, msg: ClientMessage) {
+        match msg {
+            ClientMessage::Connected(id, stream) => {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                println!("Client connected: {peer}");
+                match Client::new(stream, peer) {
+                    Ok(client) => {
+                        self.clients.insert(id, client);
+                        //every client gets the greeting the game gave us
+                        self.write(id, "<READY>");
+                        self.write(id, PROMPT_MESSAGE);
+                    }
+                    Err(e) => println!("[ERROR] {e}"),
+                }
+            }
+            ClientMessage::Command(id, line) => {
+                let line = String::from(line.trim());
+                let name = line.split_whitespace().next().unwrap_or_default();
+                if name == "exit" || name == "quit" {
+                    //one client leaving shouldn't close the game on everyone else
+                    self.write(id, "<EXIT>");
+                } else if name == "undo" || name == "redo" {
+                    //the game keeps one history for everyone, so this would take
+                    //back whatever another editor did last
+                    let msg = format!("'{name}' isn't available through the proxy.");
+                    self.write(id, &Response::Error(line, msg).to_message());
+                    self.write(id, PROMPT_MESSAGE);
+                } else {
+                    self.forward(id, line);
+                }
+            }
+            ClientMessage::Disconnected(id) => {
+                if let Some(client) = self.clients.remove(&id) {
+                    println!("Client disconnected: {}", client.peer);
+                }
+                //whatever it was still waiting for goes nowhere
+                self.owners.retain(|_, owner| *owner != id);
+            }
+            ClientMessage::Wake => {}
+        }
+    }
+
+    fn forward(&mut self, id: usize, line: String) {
+        if self.queue.connected() {
+            let ticket = self.queue.send(&line);
+            self.owners.insert(ticket, id);
+            self.lines.insert(ticket, line);
+        } else {
+            let msg = String::from("The proxy isn't connected to the server.");
+            let error = Response::Error(line, msg);
+            self.write(id, &error.to_message());
+            self.write(id, PROMPT_MESSAGE);
+        }
+    }
+
+    fn route_responses(&mut self) {
+        while let Some((tickets, response)) = self.queue.receive() {
+            //commands merged in the queue may have come from different clients
+            let ids = tickets.iter().filter_map(|t| self.owners.remove(t)).collect::<Vec<_>>();
+            let lines = tickets.iter().filter_map(|t| self.lines.remove(t));
+            let Some(line) = lines.last() else { continue };
+            for &id in &ids {
+                self.write(id, &response.to_message());
+                self.write(id, PROMPT_MESSAGE);
+            }
+            //everyone else has to find out their view of the track is stale
+            let (err, name, _, _) = response.decompose();
+            if !err && !commands::is_query(name) {
+                let event = ServerEvent::RemoteChange(line);
+                self.broadcast(&ids, &format!("{EVENT_SIGNAL} {event}"));
+            }
+        }
+    }
+
+    fn write(&mut self, id: usize, msg: &str) {
+        let Some(client) = self.clients.get(&id) else { return };
+        let reason = match client.writer.try_send(String::from(msg)) {
+            Ok(()) => return,
+            Err(TrySendError::Full(_)) => "not keeping up",
+            Err(TrySendError::Disconnected(_)) => "connection lost",
+        };
+        //its reader notices the stream closing and cleans up after it
+        println!("Client dropped ({}): {reason}", client.peer);
+        let _ = client.stream.shutdown(Shutdown::Both);
+        self.clients.remove(&id);
+    }
+
+    fn broadcast(&mut self, except: &[usize], msg: &str) {
+        let ids = self.clients.keys().copied().filter(|id| !except.contains(id));
+        for id in ids.collect::<Vec<_>>() {
+            self.write(id, msg);
+        }
+    }
+
+    fn try_connect(&mut self) {
+        if !self.reconnect.due() {
+            if self.reconnect.exhausted() {
+                self.reconnect.reset();
+            }
+            return;
+        }
+        match self.queue.connect(&self.upstream) {
+            Ok(()) => {
+                self.reconnect.reset();
+                println!("[OK] Connected to server.");
+            }
+            Err(e) => {
+                self.reconnect.failed();
+                let t = self.reconnect.remaining().unwrap_or_default().as_secs_f32();
+                println!("[ERROR] Failed to connect to server: {e} (retrying in {t:.1}s)");
+            }
+        }
+    }
+
+    fn drop_upstream(&mut self) {
+        if let Err(e) = self.queue.reset() {
+            println!("[ERROR] {e}");
+        }
+        //nothing in flight is ever going to be answered now
+        for (ticket, line) in self.lines.drain().collect::<Vec<_>>() {
+            let Some(id) = self.owners.remove(&ticket) else { continue };
+            let error = Response::Error(line, String::from("Lost the connection to the server."));
+            self.write(id, &error.to_message());
+            self.write(id, PROMPT_MESSAGE);
+        }
+        self.paused = false;
+        self.reconnect.reset();
+    }
+}
+
+fn accept(listener: TcpListener, sender: Sender<ClientMessage>) {
+    for (id, stream) in listener.incoming().enumerate() {
+        let Ok(stream) = stream else { continue };
+        let reader = match stream.set_nodelay(true).and_then(|_| stream.try_clone()) {
+            Ok(reader) => reader,
+            Err(_) => continue,
+        };
+        if sender.send(ClientMessage::Connected(id, stream)).is_err() {
+            return;
+        }
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let mut reader = FrameReader::new(reader, MAX_COMMAND_SIZE);
+            //a malformed frame is skipped, a broken connection ends the client
+            while let Ok(Some(frame)) = reader.read_frame() {
+                if let Ok(line) = frame {
+                    if sender.send(ClientMessage::Command(id, line)).is_err() {
+                        return;
+                    }
+                }
+            }
+            let _ = sender.send(ClientMessage::Disconnected(id));
+        });
+    }
+}
+
+fn write_message(stream: &mut impl Write, msg: &str) -> io::Result<()> {
+    stream.write_all(&encode_message(msg))?;
+    stream.flush()
+}
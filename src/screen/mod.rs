@@ -1,9 +1,9 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     mem,
-    net::SocketAddr,
     ops::RangeInclusive,
-    sync::mpsc::{self, Sender},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
     time::{Duration, Instant},
 };
 
@@ -12,33 +12,83 @@ use self::panels::{
     SectionsPanel,
 };
 use crate::{
-    server::{self, CommandQueue},
-    utils::UnitResult,
+    server::{
+        self, commands::TrackCommand, CommandQueue, Connection, Endpoint, ReconnectPolicy,
+        ServerEvent, ServerProcess, Ticket,
+    },
+    utils::{GenericResult, UnitResult},
 };
 
 mod panels;
 
+const IDLE_REPAINT_TIME: Duration = Duration::from_millis(100);
+//how long remote changes are collected before the panels ask for fresh state
+const REMOTE_REFRESH_TIME: Duration = Duration::from_millis(250);
+
+//a mouse gesture, like dragging a slider, is undone as a single step
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Gesture {
+    Idle,
+    Held,
+    //the last change of a drag is written a frame after the button comes up
+    Released,
+}
+
 pub struct Screen {
     enabled: bool,
     resize_frame_skip: bool,
     queue: CommandQueue,
     state_reset: HashSet<&'static str>,
     panels: Vec<Box<dyn Panel>>,
-    connection_timer: Instant,
+    owners: HashMap<Ticket, usize>,
+    reconnect: ReconnectPolicy,
+    //a connection being opened in the background
+    dialing: Option<Receiver<GenericResult<Connection>>>,
     console: Option<Sender<String>>,
-    port: u16,
+    endpoint: Endpoint,
+    process: Option<ServerProcess>,
+    //when someone else sharing the game first changed the track
+    stale: Option<Instant>,
+    gesture: Gesture,
 }
 
 pub trait StateSync {
-    fn initialize_state(&mut self, send: &mut dyn FnMut(&str));
+    fn initialize_state(&mut self, send: &mut dyn FnMut(TrackCommand));
     fn update_state(&mut self);
-    fn request_state(&self, send: &mut dyn FnMut(&str));
-    fn write_state(&mut self, send: &mut dyn FnMut(&str));
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand));
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand));
+
+    //called after every successful connection, when the server may have restarted
+    fn resync_state(&self, send: &mut dyn FnMut(TrackCommand)) { self.request_state(send) }
 }
 
 pub trait CommandHandler {
     fn should_handle(&self, command: &str) -> bool;
     fn handle(&mut self, response: &server::Response) -> UnitResult;
+
+    //the panel is greyed out when the server is missing any of these
+    fn required_commands(&self) -> &'static [&'static str] { &[] }
+
+    //names of the server events this handler wants to hear about
+    fn subscriptions(&self) -> &'static [&'static str] { &[] }
+
+    fn handle_event(
+        &mut self, _event: &ServerEvent, _send: &mut dyn FnMut(TrackCommand),
+    ) -> UnitResult {
+        Ok(())
+    }
+
+    //responses to requests sent by someone else are only handed over on request
+    fn wants_broadcast(&self, _command: &str) -> bool { false }
+
+    //called instead of handle() for responses to requests this handler sent itself
+    fn handle_own(&mut self, response: &server::Response) -> UnitResult {
+        if self.should_handle(response.identifier()) {
+            self.handle(response)
+        } else {
+            Ok(())
+        }
+    }
 }
 
 pub trait Render {
@@ -91,16 +141,29 @@ pub trait Render {
 pub trait Panel: Render + CommandHandler + StateSync {}
 
 impl Screen {
-    pub fn new(port: u16, _cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(
+        mut queue: CommandQueue,
+        endpoint: Endpoint,
+        process: Option<ServerProcess>,
+        cc: &eframe::CreationContext<'_>,
+    ) -> Self {
+        //repaint as soon as the server says something, instead of on a timer
+        let ctx = cc.egui_ctx.clone();
+        queue.set_waker(move || ctx.request_repaint());
         Screen {
-            connection_timer: Instant::now() - Duration::from_secs(10),
-            queue: CommandQueue::new(),
+            reconnect: ReconnectPolicy::default(),
+            dialing: None,
+            queue,
             panels: Vec::new(),
+            owners: HashMap::new(),
             state_reset: HashSet::new(),
             enabled: false,
             resize_frame_skip: false,
             console: None,
-            port,
+            endpoint,
+            process,
+            stale: None,
+            gesture: Gesture::Idle,
         }
     }
 
@@ -139,61 +202,189 @@ impl Screen {
         //create the console channel
         self.console.replace(t1);
         //initialize local state and sync with server
-        self.panels.iter_mut().for_each(|p| {
-            p.initialize_state(&mut |x| self.queue.send(x));
-        });
+        for (i, p) in self.panels.iter_mut().enumerate() {
+            p.initialize_state(&mut |x| {
+                self.owners.insert(self.queue.send(&x.to_string()), i);
+            });
+        }
     }
 
-    fn global_update(&mut self) -> UnitResult {
+    fn global_update(&mut self, held: bool) -> UnitResult {
         if self.panels.is_empty() {
             self.initialize();
         } else {
+            while let Some(line) = self.process.as_mut().and_then(ServerProcess::message) {
+                self.print(&format!("[SERVER] {line}"));
+            }
             if self.queue.connected() {
                 if let Err(msg) = self.queue.update() {
                     self.print(&format!("[ERROR] {msg}"));
                     self.enabled = false;
-                    let prev = mem::replace(&mut self.queue, CommandQueue::new());
-                    if let Err(msg) = prev.disconnect() {
+                    self.owners.clear();
+                    if let Err(msg) = self.queue.reset() {
                         self.print(&format!("[ERROR] {msg}"));
                     }
+                    self.reconnect.reset();
                 } else {
                     self.enabled = !self.queue.paused();
-                    if let Some(r) = self.queue.receive() {
+                    while let Some(msg) = self.queue.diagnostic() {
+                        self.print(&format!("[WARNING] {msg}"));
+                    }
+                    //everything that arrived since the last frame, so a burst of
+                    //responses doesn't trickle in one per repaint
+                    while let Some((tickets, r)) = self.queue.receive() {
                         //in this scope, r is guaranteed to be
                         //either Success or Error, never Nothing
                         let (err, id, _, _) = r.decompose();
                         //check if this command should trigger
                         //a state request from the panels
                         if !err && self.state_reset.contains(id) {
-                            self.panels
-                                .iter_mut()
-                                .for_each(|x| x.request_state(&mut |x| self.queue.send(x)));
+                            for (i, p) in self.panels.iter_mut().enumerate() {
+                                p.request_state(&mut |x| {
+                                    self.owners.insert(self.queue.send(&x.to_string()), i);
+                                });
+                            }
+                        }
+                        //the panel that sent the request always gets the
+                        //response, the others only if they asked for it
+                        //merged requests from one panel still only get one response
+                        let owners = tickets
+                            .iter()
+                            .filter_map(|t| self.owners.remove(t))
+                            .collect::<HashSet<_>>();
+                        let mut failures = Vec::new();
+                        for (i, p) in self.panels.iter_mut().enumerate() {
+                            let result = if owners.contains(&i) {
+                                p.handle_own(&r)
+                            } else if p.wants_broadcast(id) {
+                                p.handle(&r)
+                            } else {
+                                Ok(())
+                            };
+                            //one bad response shouldn't keep the other panels from seeing it
+                            if let Err(e) = result {
+                                failures.push(e.to_string());
+                            }
                         }
-                        for p in self.panels.iter_mut().filter(|x| x.should_handle(id)) {
-                            p.handle(&r)?;
+                        for e in failures {
+                            self.print(&format!("[ERROR] {e}"));
                         }
                     }
-                    for p in self.panels.iter_mut() {
-                        p.write_state(&mut |x| self.queue.send(x));
+                    while let Some(event) = self.queue.event() {
+                        self.dispatch_event(&event);
                     }
+                    //a burst of remote changes only needs one refresh, which can't wait
+                    //for our own requests to run out, as a held slider never lets them
+                    if self.stale.is_some_and(|t| t.elapsed() >= REMOTE_REFRESH_TIME) {
+                        self.stale = None;
+                        for (i, p) in self.panels.iter_mut().enumerate() {
+                            p.request_state(&mut |x| {
+                                self.owners.insert(self.queue.send(&x.to_string()), i);
+                            });
+                        }
+                    }
+                    //whatever the panels write in one frame, like "Adjust All", is one edit
+                    self.queue.begin_batch();
+                    for (i, p) in self.panels.iter_mut().enumerate() {
+                        p.write_state(&mut |x| {
+                            self.owners.insert(self.queue.send(&x.to_string()), i);
+                        });
+                    }
+                    self.queue.commit_batch();
+                    self.track_gesture(held);
                 }
-            } else if self.connection_timer.elapsed() > Duration::from_secs(3) {
-                let address = SocketAddr::from(([127, 0, 0, 1], self.port));
-                if let Err(e) = self.queue.connect(&address) {
-                    self.print(&format!("[ERROR] Failed to connect to server: {e}"));
-                    self.connection_timer = Instant::now();
-                } else {
-                    self.print("[OK] Connected to server.");
-                    //on successful connection, trigger state request
-                    for p in self.panels.iter_mut() {
-                        p.request_state(&mut |x| self.queue.send(x));
+            } else if let Some(dialing) = self.dialing.take() {
+                match dialing.try_recv() {
+                    Ok(opened) => {
+                        let r = opened.and_then(|c| self.queue.attach(&self.endpoint, c));
+                        self.connected(r);
+                    }
+                    Err(TryRecvError::Empty) => self.dialing = Some(dialing),
+                    Err(TryRecvError::Disconnected) => {
+                        self.connected(Err("The connection attempt was lost.".into()))
                     }
                 }
+            } else if self.reconnect.due() {
+                //resolving the host and trying each address can take seconds,
+                //so that happens away from the ui and the stream is handed back
+                let endpoint = self.endpoint.clone();
+                let (tx, rx) = mpsc::channel();
+                thread::spawn(move || tx.send(Connection::open(&endpoint)));
+                self.dialing = Some(rx);
             }
             self.panels.iter_mut().for_each(|x| x.update_state());
         }
         Ok(())
     }
+
+    fn connected(&mut self, r: UnitResult) {
+        if let Err(e) = r {
+            self.reconnect.failed();
+            let msg = match self.reconnect.remaining() {
+                Some(t) => format!("retrying in {:.1}s", t.as_secs_f32()),
+                None => format!("giving up after {} attempts", self.reconnect.attempts()),
+            };
+            self.print(&format!("[ERROR] Failed to connect to server: {e} ({msg})"));
+        } else {
+            self.reconnect.reset();
+            self.print("[OK] Connected to server.");
+            //the game may have restarted, so bring every panel up to date
+            for (i, p) in self.panels.iter_mut().enumerate() {
+                p.resync_state(&mut |x| {
+                    self.owners.insert(self.queue.send(&x.to_string()), i);
+                });
+            }
+        }
+    }
+
+    fn track_gesture(&mut self, held: bool) {
+        match (self.gesture, held) {
+            (Gesture::Idle, true) => {
+                self.queue.begin_batch();
+                self.gesture = Gesture::Held;
+            }
+            (Gesture::Held, false) => self.gesture = Gesture::Released,
+            (Gesture::Released, _) => {
+                self.queue.commit_batch();
+                self.gesture = Gesture::Idle;
+                if held {
+                    self.queue.begin_batch();
+                    self.gesture = Gesture::Held;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn dispatch_event(&mut self, event: &ServerEvent) {
+        if let ServerEvent::RemoteChange(_) = event {
+            self.stale.get_or_insert_with(Instant::now);
+            return;
+        }
+        let mut subscribed = false;
+        let mut failures = Vec::new();
+        for (i, p) in self.panels.iter_mut().enumerate() {
+            if !p.subscriptions().contains(&event.name()) {
+                continue;
+            }
+            subscribed = true;
+            let r = p.handle_event(event, &mut |x| {
+                self.owners.insert(self.queue.send(&x.to_string()), i);
+            });
+            if let Err(e) = r {
+                failures.push(e.to_string());
+            }
+        }
+        for e in failures {
+            self.print(&format!("[ERROR] {e}"));
+        }
+        //nobody is listening, but the user may want to know it happened
+        match event {
+            ServerEvent::Unknown(_) => self.print(&format!("[WARNING] Unknown event: {event}")),
+            _ if !subscribed => self.print(&format!("[EVENT] {event}")),
+            _ => (),
+        }
+    }
 }
 
 impl eframe::App for Screen {
@@ -202,10 +393,14 @@ impl eframe::App for Screen {
         if let Err(e) = queue.disconnect() {
             eprintln!("{:?}", e);
         }
+        if let Some(Err(e)) = self.process.as_mut().map(ServerProcess::stop) {
+            eprintln!("[ERROR] Failed to stop the server process: {e}");
+        }
     }
 
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Err(msg) = self.global_update() {
+        let held = ctx.input(|i| i.pointer.any_down());
+        if let Err(msg) = self.global_update(held) {
             eprintln!("{msg}");
         }
         const LEFT_SIDE_PANELS: usize = 4;
@@ -233,11 +428,16 @@ impl eframe::App for Screen {
             .enabled(self.enabled)
             .show(ctx, |ui| {
                 if ui.input(|i| i.modifiers.ctrl && i.key_pressed(Key::Z)) {
-                    self.queue.send(if ui.input(|i| i.modifiers.shift) {
-                        "redo"
-                    } else {
-                        "undo"
-                    });
+                    let redo = ui.input(|i| i.modifiers.shift);
+                    let command = if redo { TrackCommand::Redo } else { TrackCommand::Undo };
+                    if self.queue.capabilities().supports(command.identifier()) {
+                        //takes back the whole gesture or batch, not just its last command
+                        if redo {
+                            self.queue.redo();
+                        } else {
+                            self.queue.undo();
+                        }
+                    }
                 }
                 ui.spacing_mut().item_spacing = Vec2::from([0.0, 0.0]);
                 ui.set_width(ctx.screen_rect().width());
@@ -246,27 +446,72 @@ impl eframe::App for Screen {
                 ui.horizontal(|ui| {
                     ui.add_space(ui.available_width() / 2.0 - rw / 2.0);
                     ui.vertical(|ui| {
+                        let capabilities = self.queue.capabilities();
                         self.panels.iter_mut().take(LEFT_SIDE_PANELS).for_each(|x| {
-                            x.render(ctx, ui);
+                            let supported = capabilities.supports_all(x.required_commands());
+                            ui.add_enabled_ui(supported, |ui| x.render(ctx, ui));
                         });
                         ui.horizontal(|ui| {
                             ui.add_space(16.0);
-                            ui.label(if self.queue.connected() {
-                                RichText::new("connected")
-                                    .color(Color32::GREEN)
-                                    .strong()
-                                    .size(14.0)
+                            if self.queue.unresponsive() {
+                                ui.label(
+                                    RichText::new(format!("{} is unresponsive", self.endpoint))
+                                        .color(Color32::YELLOW)
+                                        .strong()
+                                        .size(14.0),
+                                );
+                            } else if self.queue.paused() {
+                                //edits made as the pause began go out once it's over
+                                let endpoint = &self.endpoint;
+                                let status = match self.queue.pending() {
+                                    0 => format!("{endpoint} is paused"),
+                                    1 => format!("{endpoint} is paused, 1 command waiting"),
+                                    n => format!("{endpoint} is paused, {n} commands waiting"),
+                                };
+                                ui.label(
+                                    RichText::new(status)
+                                        .color(Color32::YELLOW)
+                                        .strong()
+                                        .size(14.0),
+                                );
+                            } else if self.queue.connected() {
+                                let endpoint = &self.endpoint;
+                                let status = match self.queue.capabilities().version() {
+                                    Some(v) => format!("connected to {endpoint} (protocol {v})"),
+                                    None => format!("connected to {endpoint}"),
+                                };
+                                ui.label(
+                                    RichText::new(status)
+                                        .color(Color32::GREEN)
+                                        .strong()
+                                        .size(14.0),
+                                );
                             } else {
-                                RichText::new("disconnected")
-                                    .color(Color32::RED)
-                                    .strong()
-                                    .size(14.0)
-                            });
+                                let status = match self.reconnect.remaining() {
+                                    Some(t) if self.reconnect.attempts() > 0 => format!(
+                                        "reconnecting to {} in {}s ({}/{})",
+                                        self.endpoint,
+                                        t.as_secs() + 1,
+                                        self.reconnect.attempts(),
+                                        self.reconnect.max_attempts()
+                                    ),
+                                    Some(_) => format!("connecting to {}", self.endpoint),
+                                    None => format!("disconnected from {}", self.endpoint),
+                                };
+                                ui.label(
+                                    RichText::new(status)
+                                        .color(Color32::RED)
+                                        .strong()
+                                        .size(14.0),
+                                );
+                            }
                         });
                     });
                     ui.vertical(|ui| {
+                        let capabilities = self.queue.capabilities();
                         self.panels.iter_mut().skip(LEFT_SIDE_PANELS).for_each(|x| {
-                            x.render(ctx, ui);
+                            let supported = capabilities.supports_all(x.required_commands());
+                            ui.add_enabled_ui(supported, |ui| x.render(ctx, ui));
                         })
                     });
                 });
@@ -284,6 +529,42 @@ impl eframe::App for Screen {
                     );
                 }
             });
-        ctx.request_repaint_after(Duration::from_millis(16));
+        if !self.queue.connected() {
+            //the main window is disabled while disconnected, so
+            //this button has to live in its own layer
+            Area::new("reconnect")
+                .anchor(Align2::CENTER_CENTER, Vec2::from([0.0, 96.0]))
+                .show(ctx, |ui| {
+                    if ui.button(RichText::new("reconnect now").size(14.0)).clicked() {
+                        self.reconnect.reset();
+                    }
+                });
+        }
+        Window::new("diagnostics")
+            .default_open(false)
+            .resizable(false)
+            .anchor(Align2::RIGHT_BOTTOM, Vec2::from([-8.0, -8.0]))
+            .show(ctx, |ui| {
+                let metrics = self.queue.metrics();
+                Grid::new("metrics").striped(true).show(ui, |ui| {
+                    ["command", "count", "errors", "mean", "max", "sent", "received"]
+                        .into_iter()
+                        .for_each(|x| _ = ui.strong(x));
+                    ui.end_row();
+                    for (id, stats) in metrics.commands() {
+                        ui.label(id);
+                        ui.label(stats.count.to_string());
+                        ui.label(format!("{:.1}%", stats.error_rate() * 100.0));
+                        ui.label(format!("{:.2?}", stats.mean()));
+                        ui.label(format!("{:.2?}", stats.max));
+                        ui.label(stats.bytes_sent.to_string());
+                        ui.label(stats.bytes_received.to_string());
+                        ui.end_row();
+                    }
+                });
+                ui.label(format!("{} commands, {:.1}/s", metrics.total(), metrics.throughput()));
+            });
+        //still needed for the panels' own timers and the reconnect countdown
+        ctx.request_repaint_after(IDLE_REPAINT_TIME);
     }
 }
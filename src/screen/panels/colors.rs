@@ -1,7 +1,15 @@
 use std::{collections::HashSet, time::Duration};
 
 use super::StateMonitor;
-use crate::{screen, server, utils};
+use crate::{
+    screen,
+    server::{
+        self,
+        commands::TrackCommand,
+        parser::{FromResponse, Palette, PALETTE_SIZE},
+    },
+    utils,
+};
 
 pub struct ColorsPanel {
     state: ColorsPanelState,
@@ -12,7 +20,7 @@ pub struct ColorsPanel {
 
 #[derive(Hash)]
 struct ColorsPanelState {
-    colors: [[u8; 4]; 8],
+    colors: [[u8; 4]; PALETTE_SIZE],
 }
 
 static COLOR_NAMES: [&str; 8] = [
@@ -31,7 +39,7 @@ impl screen::Panel for ColorsPanel {}
 impl ColorsPanel {
     pub fn new() -> Self {
         let state = ColorsPanelState {
-            colors: [[0; 4]; 8],
+            colors: [[0; 4]; PALETTE_SIZE],
         };
         let monitor = StateMonitor::new();
         ColorsPanel {
@@ -46,37 +54,37 @@ impl ColorsPanel {
 impl screen::CommandHandler for ColorsPanel {
     fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
 
+    fn required_commands(&self) -> &'static [&'static str] { &["color-list", "color-set"] }
+
     fn handle(&mut self, contents: &server::Response) -> utils::UnitResult {
-        let (err, cmd, _, resp) = contents.decompose();
+        let (err, cmd, _, _) = contents.decompose();
         if !err && cmd == "color-list" {
-            for (index, line) in resp.lines().map(str::trim).enumerate() {
-                let color = u32::from_str_radix(line, 16)?;
-                self.state.colors[index] = color.to_be_bytes();
-            }
+            let Palette(colors) = Palette::from_response(contents)?;
+            self.state.colors = colors;
         }
         Ok(())
     }
 }
 
 impl screen::StateSync for ColorsPanel {
-    fn initialize_state(&mut self, _send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, _send: &mut dyn FnMut(TrackCommand)) {
         self.commands.insert("color-list");
     }
 
     fn update_state(&mut self) { self.monitor.update(&self.state); }
 
-    fn request_state(&self, send: &mut dyn FnMut(&str)) { send("color-list"); }
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand)) { send(TrackCommand::ColorList); }
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         if self.monitor.time_elapsed(Duration::from_millis(100)) {
             for i in 0..self.state.colors.len() {
                 if self.modified >> i & 1 != 0 {
                     let color = u32::from_be_bytes(self.state.colors[i]);
-                    send(format!("color-set {} 0x{:x}", i, color).as_str());
+                    send(TrackCommand::ColorSet { index: i, color });
                 }
             }
             self.modified = 0;
-            send("view-preview");
+            send(TrackCommand::ViewPreview);
             self.monitor.sleep();
         }
     }
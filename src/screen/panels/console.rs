@@ -1,6 +1,13 @@
 use std::{collections::{HashSet, VecDeque}, sync::mpsc::Receiver};
 
-use crate::{screen, server, utils::UnitResult};
+use crate::{
+    screen,
+    server::{
+        self,
+        commands::{CommandError, TrackCommand},
+    },
+    utils::UnitResult,
+};
 
 const ENTRY_LIMIT: usize = 300;
 
@@ -10,7 +17,6 @@ pub struct ConsolePanel {
     history: VecDeque<String>,
     command_buffer: String,
     command_send_flag: bool,
-    expecting: Option<String>,
     receiver: Option<Receiver<String>>
 }
 
@@ -22,7 +28,6 @@ impl ConsolePanel {
             history: VecDeque::new(),
             command_buffer: String::new(),
             command_send_flag: false,
-            expecting: None,
             receiver
         }
     }
@@ -33,20 +38,9 @@ impl ConsolePanel {
         }
         self.history.push_back(String::from(msg));
     }
-}
-
-impl screen::CommandHandler for ConsolePanel {
-    fn should_handle(&self, command: &str) -> bool { !self.strict_excludes.contains(command) }
 
-    fn handle(&mut self, contents: &server::Response) -> UnitResult {
+    fn write_response(&mut self, contents: &server::Response) {
         let (err, cmd, args, resp) = contents.decompose();
-        if let Some(e) = &self.expecting {
-            if e == cmd {
-                self.expecting.take();
-            }
-        } else if !err && self.normal_excludes.contains(cmd) {
-            return Ok(());
-        }
         let mut buffer = String::new();
         let status = if err { "ERROR" } else { "OK" };
         buffer.push_str(&format!("* {} ({}) => [{}]", cmd, args, status));
@@ -55,6 +49,28 @@ impl screen::CommandHandler for ConsolePanel {
             buffer.push_str(resp);
         }
         self.write_line(&buffer);
+    }
+}
+
+impl screen::CommandHandler for ConsolePanel {
+    fn should_handle(&self, command: &str) -> bool { !self.strict_excludes.contains(command) }
+
+    //the console logs everything that goes through the queue
+    fn wants_broadcast(&self, command: &str) -> bool { self.should_handle(command) }
+
+    fn handle(&mut self, contents: &server::Response) -> UnitResult {
+        let (err, cmd, _, _) = contents.decompose();
+        if err || !self.normal_excludes.contains(cmd) {
+            self.write_response(contents);
+        }
+        Ok(())
+    }
+
+    //commands typed by the user are always echoed back, even the quiet ones
+    fn handle_own(&mut self, contents: &server::Response) -> UnitResult {
+        if self.should_handle(contents.identifier()) {
+            self.write_response(contents);
+        }
         Ok(())
     }
 }
@@ -99,7 +115,7 @@ impl screen::Render for ConsolePanel {
 }
 
 impl screen::StateSync for ConsolePanel {
-    fn initialize_state(&mut self, _send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, _send: &mut dyn FnMut(TrackCommand)) {
         //these commands will never show up on the console
         self.strict_excludes = HashSet::from_iter(vec!["view-preview"]);
         //these won't show on the console in case of success
@@ -134,16 +150,18 @@ impl screen::StateSync for ConsolePanel {
         }
     }
 
-    fn request_state(&self, _send: &mut dyn FnMut(&str)) {}
+    fn request_state(&self, _send: &mut dyn FnMut(TrackCommand)) {}
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         if self.command_send_flag && !self.command_buffer.is_empty() {
-            self.expecting = self
-                .command_buffer
-                .split(char::is_whitespace)
-                .map(String::from)
-                .next();
-            send(&self.command_buffer);
+            //commands the editor doesn't know about go through as typed
+            match self.command_buffer.parse::<TrackCommand>() {
+                Ok(cmd) => send(cmd),
+                Err(CommandError::Unknown(_)) => {
+                    send(TrackCommand::Raw(self.command_buffer.clone()))
+                }
+                Err(e) => self.write_line(&format!("[ERROR] {e}")),
+            }
             self.command_buffer.clear();
             self.command_send_flag = false;
         }
@@ -4,7 +4,16 @@ use std::{
 };
 
 use super::{FieldFlags, StateMonitor};
-use crate::{screen, server, utils};
+use crate::{
+    screen,
+    server::{
+        self,
+        commands::TrackCommand,
+        parser::{self, FromResponse, HeaderRecord},
+        ServerEvent,
+    },
+    utils,
+};
 
 #[derive(PartialEq, Eq, Hash)]
 enum HeaderFields {
@@ -60,49 +69,53 @@ impl HeaderPanel {
 impl screen::CommandHandler for HeaderPanel {
     fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
 
+    //both this and the patterns panel keep a list of props
+    fn wants_broadcast(&self, command: &str) -> bool { command == "package-props" }
+
+    fn required_commands(&self) -> &'static [&'static str] {
+        &[
+            "header-get",
+            "header-name-set",
+            "header-background-set",
+            "header-texture-set",
+            "header-flags-set",
+            "package-list",
+        ]
+    }
+
+    fn subscriptions(&self) -> &'static [&'static str] { &["package-reloaded"] }
+
+    //the package lists may have changed under our feet
+    fn handle_event(
+        &mut self, _event: &ServerEvent, send: &mut dyn FnMut(TrackCommand),
+    ) -> utils::UnitResult {
+        screen::StateSync::request_state(self, send);
+        Ok(())
+    }
+
     fn handle(&mut self, contents: &server::Response) -> utils::UnitResult {
-        let (err, cmd, _, resp) = contents.decompose();
-        let error = Err("Failed to parse server response.".into());
+        let (err, cmd, _, _) = contents.decompose();
         if !err {
             if cmd == "header-get" {
-                for line in resp.lines().map(str::trim) {
-                    let (field, value) = match line.split_once(char::is_whitespace) {
-                        Some((f, v)) => (f, v),
-                        None => return error,
-                    };
-                    match field {
-                        "package" => self.state.package = value.to_owned(),
-                        "name" => self.state.name = value.to_owned(),
-                        "background" => self.state.background = value.to_owned(),
-                        "texture" => self.state.texture = value.to_owned(),
-                        "random-seed" => {
-                            if let Ok(v) = value.parse::<i64>() {
-                                self.state.random_seed = v
-                            } else {
-                                return error;
-                            }
-                        }
-                        "flags" => {
-                            if let Ok(v) = value.parse::<u32>() {
-                                self.state.flags = v
-                            } else {
-                                return error;
-                            }
-                        }
-                        _ => {}
-                    };
-                }
+                let header = HeaderRecord::from_response(contents)?;
+                self.state = HeaderState {
+                    package: header.package,
+                    name: header.name,
+                    background: header.background,
+                    texture: header.texture,
+                    flags: header.flags,
+                    random_seed: header.random_seed,
+                    clicks: self.state.clicks,
+                };
             } else {
                 let key = match cmd {
                     "package-list" => "packages",
                     "package-backgrounds" => "backgrounds",
                     "package-textures" => "textures",
                     "package-props" => "props",
-                    _ => return error,
+                    _ => return Err(format!("Unexpected response to '{cmd}'.").into()),
                 };
-                let list = self.content_lists.entry(key).or_default();
-                list.clear();
-                list.extend(resp.lines().map(str::trim).map(String::from).collect::<Vec<String>>());
+                self.content_lists.insert(key, parser::names(contents));
             }
         }
         Ok(())
@@ -110,7 +123,7 @@ impl screen::CommandHandler for HeaderPanel {
 }
 
 impl screen::StateSync for HeaderPanel {
-    fn initialize_state(&mut self, _send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, _send: &mut dyn FnMut(TrackCommand)) {
         self.commands.extend(vec![
             "header-get",
             "package-list",
@@ -122,15 +135,15 @@ impl screen::StateSync for HeaderPanel {
 
     fn update_state(&mut self) { self.monitor.update(&self.state); }
 
-    fn request_state(&self, send: &mut dyn FnMut(&str)) {
-        send("package-list");
-        send("package-backgrounds");
-        send("package-textures");
-        send("package-props");
-        send("header-get");
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand)) {
+        send(TrackCommand::PackageList);
+        send(TrackCommand::PackageBackgrounds);
+        send(TrackCommand::PackageTextures);
+        send(TrackCommand::PackageProps);
+        send(TrackCommand::HeaderGet);
     }
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         let mut extras = false;
         let mut play_test = false;
         if self.monitor.time_elapsed(Duration::from_millis(500)) {
@@ -142,38 +155,41 @@ impl screen::StateSync for HeaderPanel {
                     play_test = true;
                     continue;
                 }
-                send(
-                    match field {
-                        HeaderFields::Package => format!("package-load \"{}\"", self.state.package),
-                        HeaderFields::Name => format!("header-name-set \"{}\"", self.state.name),
-                        HeaderFields::Background => {
-                            format!("header-background-set \"{}\"", self.state.background)
-                        }
-                        HeaderFields::Texture => {
-                            format!("header-texture-set \"{}\"", self.state.texture)
-                        }
-                        HeaderFields::Flags => {
-                            format!("header-flags-set {:#012b}", self.state.flags)
-                        }
-                        HeaderFields::RandomSeed => String::from("header-new-random-seed"),
-                        HeaderFields::PlayTest => unreachable!(),
-                    }
-                    .as_str(),
-                );
+                let s = &self.state;
+                send(match field {
+                    HeaderFields::Package => TrackCommand::PackageLoad {
+                        name: s.package.clone(),
+                    },
+                    HeaderFields::Name => TrackCommand::HeaderNameSet {
+                        name: s.name.clone(),
+                    },
+                    HeaderFields::Background => TrackCommand::HeaderBackgroundSet {
+                        background: s.background.clone(),
+                    },
+                    HeaderFields::Texture => TrackCommand::HeaderTextureSet {
+                        texture: s.texture.clone(),
+                    },
+                    HeaderFields::Flags => TrackCommand::HeaderFlagsSet { flags: s.flags },
+                    HeaderFields::RandomSeed => TrackCommand::HeaderNewRandomSeed,
+                    HeaderFields::PlayTest => unreachable!(),
+                });
             }
             if request {
-                send("header-get");
+                send(TrackCommand::HeaderGet);
             }
             if extras {
-                send("package-backgrounds");
-                send("package-textures");
-                send("package-props");
+                send(TrackCommand::PackageBackgrounds);
+                send(TrackCommand::PackageTextures);
+                send(TrackCommand::PackageProps);
             }
             if play_test {
                 if self.autosave {
-                    send("project-save");
+                    send(TrackCommand::ProjectSave {
+                        name: None,
+                        confirm: None,
+                    });
                 }
-                send("track-play-test");
+                send(TrackCommand::TrackPlayTest);
             }
             self.monitor.sleep();
         }
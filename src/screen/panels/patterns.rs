@@ -5,7 +5,16 @@ use std::{
 };
 
 use super::{FieldFlags, StateMonitor};
-use crate::{screen, server, utils};
+use crate::{
+    screen,
+    server::{
+        self,
+        commands::{PatternValues, TrackCommand},
+        parser::{self, FromResponse, PatternRecord},
+        ServerEvent,
+    },
+    utils,
+};
 
 static CHECKBOXES: [&str; 5] = ["Mirror", "Flip", "Sine", "Random Flip", "Sync Offset"];
 static BUTTONS: LazyLock<Vec<(&'static str, Fields)>> = LazyLock::new(|| {
@@ -38,7 +47,7 @@ enum Fields {
 pub struct PatternsPanel {
     state: PatternsState,
     prop_cache: Vec<String>,
-    pattern_cache: Vec<(String, String)>,
+    pattern_cache: Vec<(PatternRecord, String)>,
     monitor: StateMonitor<PatternsState>,
     modified: FieldFlags<Fields>,
     receiver: Receiver<(usize, i32)>,
@@ -47,18 +56,6 @@ pub struct PatternsPanel {
     scroll_to: Option<usize>,
 }
 
-struct Pattern {
-    prop: String,
-    position: i32,
-    size: i32,
-    spacing: i32,
-    x: i32,
-    freq: i32,
-    amp: i32,
-    offset: i32,
-    flags: u32,
-}
-
 #[derive(Default, Hash)]
 struct PatternsState {
     section: usize,
@@ -75,8 +72,8 @@ struct PatternsState {
     clicks: u8,
 }
 
-impl From<&Pattern> for PatternsState {
-    fn from(value: &Pattern) -> Self {
+impl From<&PatternRecord> for PatternsState {
+    fn from(value: &PatternRecord) -> Self {
         PatternsState {
             section: 0,
             index: 0,
@@ -112,10 +109,7 @@ impl PatternsPanel {
     }
 
     fn update_sliders(&mut self) {
-        if self.pattern_cache.is_empty() {
-            self.state = Default::default();
-        } else if let Some(p) = Self::extract_pattern_data(&self.pattern_cache[self.state.index].0)
-        {
+        if let Some((p, _)) = self.pattern_cache.get(self.state.index) {
             let prop = self
                 .prop_cache
                 .iter()
@@ -125,83 +119,70 @@ impl PatternsPanel {
                 let s = &self.state;
                 (s.section, s.index, s.clicks)
             };
-            self.state = PatternsState::from(&p);
+            self.state = PatternsState::from(p);
             self.state.prop = prop;
             self.state.section = section;
             self.state.index = index;
             self.state.clicks = clicks;
+        } else {
+            self.state = Default::default();
         }
     }
 
-    fn format_pattern_setter(&self) -> String {
+    fn pattern_setter(&self) -> TrackCommand {
         let s = &self.state;
-        format!(
-            "pattern-set {} {} {} {} {} {} {} {} {} {} {}",
-            s.section,
-            s.index,
-            self.prop_cache[s.prop],
-            s.position,
-            s.size,
-            s.spacing,
-            s.x * X_SCALAR,
-            s.freq,
-            s.amp * X_SCALAR,
-            s.offset,
-            s.flags
-        )
+        TrackCommand::PatternSet {
+            section: s.section,
+            index: s.index,
+            pattern: PatternValues {
+                prop: self.prop_cache[s.prop].clone(),
+                position: s.position,
+                size: s.size,
+                spacing: s.spacing,
+                x: s.x * X_SCALAR,
+                freq: s.freq,
+                amp: s.amp * X_SCALAR,
+                offset: s.offset,
+                flags: s.flags,
+            },
+        }
     }
 
-    fn format_pattern_data(index: usize, pattern: &Pattern) -> String {
+    fn format_pattern_data(index: usize, pattern: &PatternRecord) -> String {
         format!("#{} :: {} ({})", index, pattern.prop, pattern.position)
     }
+}
 
-    fn extract_pattern_data(pattern: &str) -> Option<Pattern> {
-        let parts = pattern
-            .split(char::is_whitespace)
-            .skip(3)
-            .map(str::trim)
-            .collect::<Vec<&str>>();
-        let prop = String::from(parts[0]);
-        let mut numbers = Vec::new();
+impl screen::CommandHandler for PatternsPanel {
+    fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
 
-        for n in parts.iter().skip(1).take(parts.len() - 1) {
-            numbers.push(n.parse::<i32>());
-        }
+    //both this and the header panel keep a list of props
+    fn wants_broadcast(&self, command: &str) -> bool { command == "package-props" }
 
-        let flags = parts[parts.len() - 1].parse::<u32>();
-        if numbers.iter().all(Result::is_ok) && flags.is_ok() {
-            let r = numbers
-                .into_iter()
-                .map(Result::unwrap)
-                .collect::<Vec<i32>>();
-            Some(Pattern {
-                prop,
-                position: r[0],
-                size: r[1],
-                spacing: r[2],
-                x: r[3],
-                freq: r[4],
-                amp: r[5],
-                offset: r[6],
-                flags: flags.unwrap(),
-            })
-        } else {
-            None
-        }
+    fn required_commands(&self) -> &'static [&'static str] {
+        &[
+            "pattern-list",
+            "pattern-set",
+            "pattern-add",
+            "pattern-delete",
+        ]
     }
-}
 
-impl screen::CommandHandler for PatternsPanel {
-    fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
+    fn subscriptions(&self) -> &'static [&'static str] { &["package-reloaded"] }
+
+    fn handle_event(
+        &mut self, _event: &ServerEvent, send: &mut dyn FnMut(TrackCommand),
+    ) -> utils::UnitResult {
+        send(TrackCommand::PackageProps);
+        Ok(())
+    }
 
     fn handle(&mut self, response: &server::Response) -> utils::UnitResult {
-        use super::EMPTY_SIGNAL;
-        let (err, cmd, _, resp) = response.decompose();
+        let (err, cmd, _, _) = response.decompose();
         if cmd == "package-props" {
             self.prop_cache.clear();
-            if !err && !resp.starts_with(EMPTY_SIGNAL) {
-                self.prop_cache
-                    .extend(resp.lines().map(str::trim).map(String::from));
+            if !err {
+                self.prop_cache = parser::names(response);
             }
         } else if cmd == "pattern-add" {
             self.scroll_to.replace(self.pattern_cache.len());
@@ -209,17 +190,15 @@ impl screen::CommandHandler for PatternsPanel {
             self.scroll_to.replace(self.state.index + 1);
         } else if cmd == "pattern-list" {
             self.pattern_cache.clear();
-            if !err && !resp.starts_with(EMPTY_SIGNAL) {
-                self.pattern_cache
-                    .extend(
-                        resp.lines()
-                            .map(str::trim)
-                            .enumerate()
-                            .filter_map(|(i, x)| {
-                                Self::extract_pattern_data(x)
-                                    .map(|p| (String::from(x), Self::format_pattern_data(i, &p)))
-                            }),
-                    );
+            if !err {
+                self.pattern_cache = Vec::<PatternRecord>::from_response(response)?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        let f = Self::format_pattern_data(i, &p);
+                        (p, f)
+                    })
+                    .collect();
             }
             let s = &mut self.state;
             let n = if let Some(scroll) = self.scroll_to.take() {
@@ -236,7 +215,7 @@ impl screen::CommandHandler for PatternsPanel {
 }
 
 impl screen::StateSync for PatternsPanel {
-    fn initialize_state(&mut self, _send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, _send: &mut dyn FnMut(TrackCommand)) {
         self.commands.extend(vec![
             "package-props",
             "section-list",
@@ -261,46 +240,48 @@ impl screen::StateSync for PatternsPanel {
         }
     }
 
-    fn request_state(&self, send: &mut dyn FnMut(&str)) {
-        send("package-props");
-        send(&format!("pattern-list {}", self.state.section));
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand)) {
+        send(TrackCommand::PackageProps);
+        send(TrackCommand::PatternList {
+            section: self.state.section,
+        });
     }
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         if self.monitor.time_elapsed(Duration::from_millis(120)) {
             let section = self.state.section;
-            let pattern = self.state.index;
+            let index = self.state.index;
             let (mut setter, mut sliders) = (false, false);
             for field in self.modified.drain() {
                 match field {
-                    Fields::Add => send(&format!("pattern-add {}", section)),
-                    Fields::Delete => send(&format!("pattern-delete {} {}", section, pattern)),
-                    Fields::Duplicate => {
-                        send(&format!("pattern-duplicate {} {}", section, pattern))
-                    }
-                    Fields::AdjustOne => send(&format!("pattern-adjust {} {}", section, pattern)),
+                    Fields::Add => send(TrackCommand::PatternAdd { section }),
+                    Fields::Delete => send(TrackCommand::PatternDelete { section, index }),
+                    Fields::Duplicate => send(TrackCommand::PatternDuplicate { section, index }),
+                    Fields::AdjustOne => send(TrackCommand::PatternAdjust { section, index }),
                     Fields::AdjustAll => {
                         for (i, _) in self.pattern_cache.iter().enumerate() {
-                            send(&format!("pattern-adjust {} {}", section, i));
+                            send(TrackCommand::PatternAdjust { section, index: i });
                         }
                     }
-                    Fields::CopyPrev => {
-                        send(&format!("pattern-copy-all {} {}", section, section - 1))
-                    }
-                    Fields::CopyNext => {
-                        send(&format!("pattern-copy-all {} {}", section, section + 1))
-                    }
+                    Fields::CopyPrev => send(TrackCommand::PatternCopyAll {
+                        section,
+                        source: section - 1,
+                    }),
+                    Fields::CopyNext => send(TrackCommand::PatternCopyAll {
+                        section,
+                        source: section + 1,
+                    }),
                     Fields::Select => sliders = true,
                     Fields::Edit => setter = true,
                 }
             }
             if setter {
-                send(&self.format_pattern_setter());
+                send(self.pattern_setter());
             }
             if sliders {
                 self.update_sliders();
             }
-            send(&format!("pattern-list {}", section));
+            send(TrackCommand::PatternList { section });
             self.monitor.sleep();
         }
     }
@@ -3,7 +3,16 @@ use std::{borrow::Cow, collections::HashSet, time::Duration};
 use base64::Engine;
 
 use super::{FieldFlags, StateMonitor};
-use crate::{screen, server, utils};
+use crate::{
+    screen,
+    server::{
+        self,
+        commands::TrackCommand,
+        parser::{FromResponse, ViewState},
+        ServerEvent,
+    },
+    utils,
+};
 
 const IDLE_PREVIEW_TIME: u64 = 480;
 const CHANGE_PREVIEW_TIME: u64 = 80;
@@ -43,6 +52,13 @@ impl PreviewPanel {
             modified: FieldFlags::new(),
         }
     }
+
+    fn preview_size() -> TrackCommand {
+        TrackCommand::ViewPreviewSize {
+            width: PREVIEW_SIZE[0] as u32,
+            height: PREVIEW_SIZE[1] as u32,
+        }
+    }
 }
 
 impl screen::Panel for PreviewPanel {}
@@ -50,28 +66,39 @@ impl screen::Panel for PreviewPanel {}
 impl screen::CommandHandler for PreviewPanel {
     fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
 
+    //the other panels move the view and change what the preview shows
+    fn wants_broadcast(&self, command: &str) -> bool {
+        matches!(command, "view-preview" | "view-position")
+    }
+
+    fn required_commands(&self) -> &'static [&'static str] { &["view-preview", "view-state-info"] }
+
+    fn subscriptions(&self) -> &'static [&'static str] { &["play-test-finished", "view-moved"] }
+
+    fn handle_event(
+        &mut self, event: &ServerEvent, send: &mut dyn FnMut(TrackCommand),
+    ) -> utils::UnitResult {
+        match event {
+            //the camera was moved from inside the game
+            ServerEvent::ViewMoved { x, z } => {
+                self.state.view_x = x / SLIDER_SCALE;
+                self.state.view_z = z / SLIDER_SCALE;
+                send(TrackCommand::ViewPreview);
+            }
+            _ => screen::StateSync::request_state(self, send),
+        }
+        Ok(())
+    }
+
     fn handle(&mut self, contents: &server::Response) -> utils::UnitResult {
         let (err, cmd, args, resp) = contents.decompose();
         if !err {
             if cmd == "view-state-info" {
-                for line in resp.lines().map(str::trim) {
-                    if let Some((field, val)) = line.split_once(char::is_whitespace) {
-                        match field {
-                            "position" => {
-                                let coords = val
-                                    .split_once(char::is_whitespace)
-                                    .map(|(x, z)| (x.parse::<i32>(), z.parse::<i32>()));
-                                if let Some((Ok(x), Ok(z))) = coords {
-                                    self.state.view_x = x / SLIDER_SCALE;
-                                    self.state.view_z = z / SLIDER_SCALE;
-                                }
-                            }
-                            "reverse" => self.state.reverse = val.starts_with("#t"),
-                            "overview" => self.state.overview = val.starts_with("#t"),
-                            _ => {}
-                        }
-                    }
-                }
+                let view = ViewState::from_response(contents)?;
+                self.state.view_x = view.x / SLIDER_SCALE;
+                self.state.view_z = view.z / SLIDER_SCALE;
+                self.state.reverse = view.reverse;
+                self.state.overview = view.overview;
             } else if cmd == "view-position" {
                 let coords = args
                     .split_once(char::is_whitespace)
@@ -91,49 +118,47 @@ impl screen::CommandHandler for PreviewPanel {
 }
 
 impl screen::StateSync for PreviewPanel {
-    fn initialize_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         self.commands
             .extend(vec!["view-preview", "view-position", "view-state-info"]);
-        let (w, h) = (PREVIEW_SIZE[0] as u32, PREVIEW_SIZE[1] as u32);
-        send(format!("view-preview-size {} {}", w, h).as_str());
-        send("view-state-info");
+        send(TrackCommand::ViewStateInfo);
     }
 
     fn update_state(&mut self) { self.monitor.update(&self.state); }
 
-    fn request_state(&self, send: &mut dyn FnMut(&str)) {
-        send("view-preview");
-        send("view-state-info");
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand)) {
+        send(TrackCommand::ViewPreview);
+        send(TrackCommand::ViewStateInfo);
+    }
+
+    fn resync_state(&self, send: &mut dyn FnMut(TrackCommand)) {
+        //a restarted game renders at its default size until told otherwise
+        send(Self::preview_size());
+        self.request_state(send);
     }
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         let t = Duration::from_millis(CHANGE_PREVIEW_TIME);
         if self.monitor.time_elapsed(t) {
             let mut extra = false;
             for field in self.modified.drain() {
                 extra |= matches!(field, Fields::View);
-                send(
-                    match field {
-                        Fields::View => {
-                            format!(
-                                "view-position {} {}",
-                                self.state.view_x * SLIDER_SCALE,
-                                self.state.view_z * SLIDER_SCALE
-                            )
-                        }
-                        Fields::Reverse => {
-                            format!("track-reverse {}", utils::bool_string(self.state.reverse))
-                        }
-                        Fields::Overview => {
-                            format!("view-overview {}", utils::bool_string(self.state.overview))
-                        }
-                    }
-                    .as_str(),
-                );
+                send(match field {
+                    Fields::View => TrackCommand::ViewPosition {
+                        x: self.state.view_x * SLIDER_SCALE,
+                        z: self.state.view_z * SLIDER_SCALE,
+                    },
+                    Fields::Reverse => TrackCommand::TrackReverse {
+                        enabled: self.state.reverse,
+                    },
+                    Fields::Overview => TrackCommand::ViewOverview {
+                        enabled: self.state.overview,
+                    },
+                });
             }
-            send("view-preview");
+            send(TrackCommand::ViewPreview);
             if extra {
-                send("section-metrics");
+                send(TrackCommand::SectionMetrics);
             }
             self.monitor
                 .advance(Duration::from_millis(IDLE_PREVIEW_TIME) - t);
@@ -1,6 +1,10 @@
 use std::collections::{HashSet, VecDeque};
 
-use crate::{screen, server, utils};
+use crate::{
+    screen,
+    server::{self, commands::TrackCommand, parser},
+    utils,
+};
 
 #[derive(Debug)]
 enum ProjectAction {
@@ -79,20 +83,24 @@ impl ProjectPanel {
 impl screen::CommandHandler for ProjectPanel {
     fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
 
+    fn required_commands(&self) -> &'static [&'static str] {
+        &[
+            "project-list",
+            "project-file-name",
+            "project-new",
+            "project-load",
+            "project-save",
+        ]
+    }
+
     fn handle(&mut self, response: &server::Response) -> utils::UnitResult {
-        use super::EMPTY_SIGNAL;
-        let (err, cmd, args, resp) = response.decompose();
+        let (err, cmd, args, _) = response.decompose();
         if cmd == "project-list" {
-            self.project_cache.clear();
-            if resp.trim() != EMPTY_SIGNAL {
-                self.project_cache
-                    .extend(resp.lines().map(str::trim).map(String::from));
-            }
+            self.project_cache = parser::names(response);
         } else if cmd == "project-file-name" {
-            if resp.trim() != EMPTY_SIGNAL {
-                let s = String::from(resp.trim());
+            if let Some(s) = parser::names(response).into_iter().next() {
                 self.project_file_name.replace(s.clone());
-                self.name_buffer = s.clone();
+                self.name_buffer = s;
             } else {
                 self.name_buffer.clear();
                 self.project_file_name.take();
@@ -120,7 +128,7 @@ impl screen::CommandHandler for ProjectPanel {
 }
 
 impl screen::StateSync for ProjectPanel {
-    fn initialize_state(&mut self, _send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, _send: &mut dyn FnMut(TrackCommand)) {
         self.commands.extend(vec![
             "project-new",
             "project-load",
@@ -133,50 +141,46 @@ impl screen::StateSync for ProjectPanel {
 
     fn update_state(&mut self) {}
 
-    fn request_state(&self, send: &mut dyn FnMut(&str)) {
-        send("project-list");
-        send("project-file-name");
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand)) {
+        send(TrackCommand::ProjectList);
+        send(TrackCommand::ProjectFileName);
     }
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         if self.execute.take().is_some() && !self.actions.is_empty() {
             let mut pop_next_action = false;
             match &self.actions[0] {
-                ProjectAction::New => match self.confirmed {
-                    None => {
-                        send("project-new");
-                    }
-                    Some(c) => send(&format!("project-new {}", utils::bool_string(c))),
-                },
+                ProjectAction::New => send(TrackCommand::ProjectNew {
+                    confirm: self.confirmed,
+                }),
                 ProjectAction::Refresh => {
-                    send("project-list");
+                    send(TrackCommand::ProjectList);
                     pop_next_action = true;
                 }
                 ProjectAction::Load(n) => {
                     if self.project_cache.len() > *n {
-                        match self.confirmed {
-                            None => send(&format!("project-load \"{}\"", self.project_cache[*n])),
-                            Some(c) => send(&format!(
-                                "project-load \"{}\" {}",
-                                self.project_cache[*n],
-                                utils::bool_string(c)
-                            )),
-                        };
+                        send(TrackCommand::ProjectLoad {
+                            name: self.project_cache[*n].clone(),
+                            confirm: self.confirmed,
+                        });
                     }
                 }
-                ProjectAction::Save(None) => {
-                    send("project-save");
-                }
-                ProjectAction::Save(Some(n)) => match self.confirmed {
-                    None => send(&format!("project-save \"{}\"", n)),
-                    Some(c) => send(&format!("project-save \"{}\" {}", n, utils::bool_string(c))),
-                },
+                ProjectAction::Save(None) => send(TrackCommand::ProjectSave {
+                    name: None,
+                    confirm: None,
+                }),
+                ProjectAction::Save(Some(n)) => send(TrackCommand::ProjectSave {
+                    name: Some(n.clone()),
+                    confirm: self.confirmed,
+                }),
                 ProjectAction::Delete(n) => match self.confirmed {
                     None => {
                         self.dialog_window.replace(DialogWindow::Delete);
                     }
                     Some(true) => {
-                        send(&format!("project-delete \"{}\"", self.project_cache[*n]));
+                        send(TrackCommand::ProjectDelete {
+                            name: self.project_cache[*n].clone(),
+                        });
                     }
                     Some(false) => {
                         pop_next_action = true;
@@ -6,7 +6,15 @@ use std::{
 };
 
 use super::{FieldFlags, StateMonitor};
-use crate::{screen, server, utils::UnitResult};
+use crate::{
+    screen,
+    server::{
+        self,
+        commands::TrackCommand,
+        parser::{FromResponse, SectionRecord},
+    },
+    utils::UnitResult,
+};
 
 const LENGTH_SCALAR: i32 = 25;
 static BUTTONS: LazyLock<Vec<(&'static str, Fields)>> = LazyLock::new(|| {
@@ -32,7 +40,7 @@ enum Fields {
 }
 
 pub struct SectionsPanel {
-    cache: Vec<(String, String)>,
+    cache: Vec<(SectionRecord, String)>,
     state: SectionsState,
     commands: HashSet<&'static str>,
     monitor: StateMonitor<SectionsState>,
@@ -75,16 +83,12 @@ impl SectionsPanel {
     fn update_sliders(&mut self) {
         let selected = self.state.selected;
         let clicks = self.state.clicks;
-        if self.cache.is_empty() {
-            self.state = Default::default();
-        } else if let Some((length, curve, slope, split)) =
-            Self::extract_section_data(&self.cache[selected].0)
-        {
+        if let Some((section, _)) = self.cache.get(selected) {
             self.state = SectionsState {
-                length: length / LENGTH_SCALAR,
-                curve,
-                slope,
-                split,
+                length: section.length / LENGTH_SCALAR,
+                curve: section.curve,
+                slope: section.slope,
+                split: section.split,
                 selected,
                 clicks,
             }
@@ -93,82 +97,68 @@ impl SectionsPanel {
         }
     }
 
-    fn format_section_data(index: usize, section: &str) -> Option<String> {
-        if let Some((length, curve, slope, split)) = Self::extract_section_data(section) {
-            let curve_type = match curve {
-                0 => "straight",
-                x if x < -2 => "sharp left",
-                x if x > 2 => "sharp right",
-                x if x < 0 => "wide left",
-                x if x > 0 => "wide right",
-                _ => unreachable!(),
-            };
-            let slope_type = match slope {
-                0 => "flat",
-                x if x > 0 => "upward",
-                x if x < 0 => "downward",
-                _ => unreachable!(),
-            };
-            let num_lanes = match split {
-                0 => "3 lanes",
-                1 => "4 lanes",
-                2 => "5 lanes",
-                _ => "dual tracks",
-            };
-            let b = format!(
-                "[{:2}] {:11} {:12} {:9} {:12}",
-                index,
-                format!("length {}", length),
-                curve_type,
-                slope_type,
-                num_lanes
-            );
-            Some(b)
-        } else {
-            None
-        }
-    }
-
-    fn extract_section_data(section: &str) -> Option<(i32, i32, i32, i32)> {
-        let split = section
-            .split(char::is_whitespace)
-            .skip(2)
-            .collect::<Vec<&str>>();
-        if let (Ok(length), Ok(curve), Ok(slope), Ok(split)) = (
-            split[0].parse::<i32>(),
-            split[1].parse::<i32>(),
-            split[2].parse::<i32>(),
-            split[3].parse::<i32>(),
-        ) {
-            Some((length, curve, slope, split))
-        } else {
-            None
-        }
+    fn format_section_data(index: usize, section: &SectionRecord) -> String {
+        let curve_type = match section.curve {
+            0 => "straight",
+            x if x < -2 => "sharp left",
+            x if x > 2 => "sharp right",
+            x if x < 0 => "wide left",
+            x if x > 0 => "wide right",
+            _ => unreachable!(),
+        };
+        let slope_type = match section.slope {
+            0 => "flat",
+            x if x > 0 => "upward",
+            x if x < 0 => "downward",
+            _ => unreachable!(),
+        };
+        let num_lanes = match section.split {
+            0 => "3 lanes",
+            1 => "4 lanes",
+            2 => "5 lanes",
+            _ => "dual tracks",
+        };
+        format!(
+            "[{:2}] {:11} {:12} {:9} {:12}",
+            index,
+            format!("length {}", section.length),
+            curve_type,
+            slope_type,
+            num_lanes
+        )
     }
 }
 
 impl screen::CommandHandler for SectionsPanel {
     fn should_handle(&self, command: &str) -> bool { self.commands.contains(command) }
 
+    //the preview panel asks for the metrics when the view moves
+    fn wants_broadcast(&self, command: &str) -> bool { command == "section-metrics" }
+
+    fn required_commands(&self) -> &'static [&'static str] {
+        &[
+            "section-list",
+            "section-set",
+            "section-add",
+            "section-delete",
+        ]
+    }
+
     fn handle(&mut self, response: &server::Response) -> UnitResult {
         let (err, cmd, args, resp) = response.decompose();
         if !err {
             if cmd == "section-add" {
                 self.scroll_to.replace(self.state.selected + 1);
             } else if cmd == "section-list" {
-                use super::EMPTY_SIGNAL;
-                self.cache.clear();
-                if !resp.starts_with(EMPTY_SIGNAL) {
-                    resp.lines()
-                        .map(str::trim)
-                        .map(String::from)
-                        .enumerate()
-                        .for_each(|(i, s)| {
-                            if let Some(f) = Self::format_section_data(i, &s) {
-                                self.cache.push((s, f));
-                            }
-                        });
-                }
+                let sections = Vec::<SectionRecord>::from_response(response)?;
+                self.cache = sections
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, s)| {
+                        let f = Self::format_section_data(i, &s);
+                        (s, f)
+                    })
+                    .collect();
                 let n = if let Some(s) = self.scroll_to.take() {
                     s
                 } else {
@@ -202,17 +192,16 @@ impl screen::CommandHandler for SectionsPanel {
                 if let Some(coords) = args.split_once(char::is_whitespace) {
                     if let Ok(mut view_z) = coords.1.parse::<i32>() {
                         let mut target_section = 0;
-                        for section in &self.cache {
-                            if let Some((len, _, _, _)) = Self::extract_section_data(&section.0) {
-                                if view_z - len <= 0 {
-                                    break;
-                                } else {
-                                    view_z -= len;
-                                    target_section += 1;
-                                }
+                        for (section, _) in &self.cache {
+                            if view_z - section.length <= 0 {
+                                break;
+                            } else {
+                                view_z -= section.length;
+                                target_section += 1;
                             }
                         }
-                        let next_selected = cmp::min(self.cache.len() - 1, target_section);
+                        let next_selected =
+                            cmp::min(self.cache.len().saturating_sub(1), target_section);
                         if next_selected != self.state.selected {
                             self.state.selected = next_selected;
                             self.modified.flag(Fields::Select);
@@ -226,7 +215,7 @@ impl screen::CommandHandler for SectionsPanel {
 }
 
 impl screen::StateSync for SectionsPanel {
-    fn initialize_state(&mut self, _send: &mut dyn FnMut(&str)) {
+    fn initialize_state(&mut self, _send: &mut dyn FnMut(TrackCommand)) {
         self.commands.extend(vec![
             "section-add",
             "section-list",
@@ -247,28 +236,28 @@ impl screen::StateSync for SectionsPanel {
         }
     }
 
-    fn request_state(&self, send: &mut dyn FnMut(&str)) {
-        send("section-list");
-        send("section-metrics");
+    fn request_state(&self, send: &mut dyn FnMut(TrackCommand)) {
+        send(TrackCommand::SectionList);
+        send(TrackCommand::SectionMetrics);
     }
 
-    fn write_state(&mut self, send: &mut dyn FnMut(&str)) {
+    fn write_state(&mut self, send: &mut dyn FnMut(TrackCommand)) {
         if self.monitor.time_elapsed(Duration::from_millis(80)) {
             let (mut request, mut sliders, mut view) = (false, false, false);
-            let s = &self.state.selected;
+            let index = self.state.selected;
             for field in self.modified.drain() {
                 request = true;
                 match field {
                     Fields::Select => sliders = true,
-                    Fields::Add => send(&format!("section-add {}", s + 1)),
-                    Fields::Delete => send(&format!("section-delete {}", s)),
-                    Fields::Duplicate => send(&format!("section-duplicate {}", s)),
+                    Fields::Add => send(TrackCommand::SectionAdd { index: index + 1 }),
+                    Fields::Delete => send(TrackCommand::SectionDelete { index }),
+                    Fields::Duplicate => send(TrackCommand::SectionDuplicate { index }),
                     Fields::MoveUp => {
-                        send(&format!("section-move {} -1", s));
+                        send(TrackCommand::SectionMove { index, offset: -1 });
                         let _ = self.last_move.replace(-1);
                     }
                     Fields::MoveDown => {
-                        send(&format!("section-move {} 1", s));
+                        send(TrackCommand::SectionMove { index, offset: 1 });
                         let _ = self.last_move.replace(1);
                     }
                     Fields::View => {
@@ -276,14 +265,13 @@ impl screen::StateSync for SectionsPanel {
                     }
                     Fields::Edit => {
                         let s = &self.state;
-                        send(&format!(
-                            "section-set {} {} {} {} {}",
-                            s.selected,
-                            s.length * LENGTH_SCALAR,
-                            s.curve,
-                            s.slope,
-                            s.split
-                        ));
+                        send(TrackCommand::SectionSet {
+                            index,
+                            length: s.length * LENGTH_SCALAR,
+                            curve: s.curve,
+                            slope: s.slope,
+                            split: s.split,
+                        });
                     }
                 };
             }
@@ -291,25 +279,21 @@ impl screen::StateSync for SectionsPanel {
                 self.update_sliders();
             }
             if view {
-                send(&format!(
-                    "view-position 0 {}",
-                    self.cache
+                send(TrackCommand::ViewPosition {
+                    x: 0,
+                    z: self
+                        .cache
                         .iter()
-                        .map(|x| {
-                            if let Some(d) = Self::extract_section_data(&x.0) {
-                                d.0
-                            } else {
-                                0
-                            }
-                        })
+                        .map(|(s, _)| s.length)
                         .take(self.state.selected)
-                        .sum::<i32>() + 1
-                ))
+                        .sum::<i32>()
+                        + 1,
+                })
             }
             if request {
-                send("section-list");
-                send("section-metrics");
-                send("view-preview");
+                send(TrackCommand::SectionList);
+                send(TrackCommand::SectionMetrics);
+                send(TrackCommand::ViewPreview);
             }
             self.monitor.sleep();
         }
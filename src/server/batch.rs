@@ -0,0 +1,96 @@
+//brackets a group of edits the server should keep as a single undo step;
+//optional, older servers don't list them among their commands
+pub const BATCH_BEGIN: &str = "batch-begin";
+pub const BATCH_END: &str = "batch-end";
+pub const UNDO: &str = "undo";
+pub const REDO: &str = "redo";
+
+//a group of edits that should be undone together
+#[derive(Debug)]
+pub struct Batch {
+    pub group: u64,
+    pub depth: usize,
+    //grouped by the server itself, instead of by repeating undo
+    pub server_side: bool,
+    //whether batch-begin went out, it's only sent ahead of the first edit
+    pub begun: bool,
+}
+
+//the server keeps one undo step per edit; this remembers which of those steps
+//belong together, for servers that can't group edits on their own
+#[derive(Debug, Default)]
+pub struct UndoGroups {
+    undo: Vec<(u64, usize)>,
+    redo: Vec<(u64, usize)>,
+}
+
+impl UndoGroups {
+    //an edit from the given group was applied
+    pub fn record(&mut self, group: u64) {
+        match self.undo.last_mut() {
+            Some((g, n)) if *g == group => *n += 1,
+            _ => self.undo.push((group, 1)),
+        }
+        self.redo.clear();
+    }
+
+    //how many times undo has to be sent to take back the last group
+    pub fn undo(&mut self) -> usize {
+        let (group, n) = self.undo.pop().unwrap_or((0, 1));
+        self.redo.push((group, n));
+        n
+    }
+
+    pub fn redo(&mut self) -> usize {
+        let (group, n) = self.redo.pop().unwrap_or((0, 1));
+        self.undo.push((group, n));
+        n
+    }
+
+    //once the history was changed behind our back, every step stands alone
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_of_a_group_are_undone_together() {
+        let mut groups = UndoGroups::default();
+        groups.record(1);
+        groups.record(2);
+        groups.record(2);
+        groups.record(2);
+        assert_eq!(groups.undo(), 3);
+        assert_eq!(groups.undo(), 1);
+        assert_eq!(groups.redo(), 1);
+        assert_eq!(groups.redo(), 3);
+        assert_eq!(groups.undo(), 3);
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_history() {
+        let mut groups = UndoGroups::default();
+        groups.record(1);
+        groups.record(1);
+        assert_eq!(groups.undo(), 2);
+        groups.record(2);
+        //nothing left to redo, so it's a single step
+        assert_eq!(groups.redo(), 1);
+    }
+
+    #[test]
+    fn unknown_history_is_one_step_at_a_time() {
+        let mut groups = UndoGroups::default();
+        assert_eq!(groups.undo(), 1);
+        groups.record(3);
+        groups.record(3);
+        groups.clear();
+        assert_eq!(groups.undo(), 1);
+        assert_eq!(groups.redo(), 1);
+    }
+}
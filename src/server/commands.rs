@@ -0,0 +1,497 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use super::queue::{quote, tokenize};
+use crate::utils;
+
+//every command known to the editor, with its arguments as shown to users
+pub static COMMANDS: [(&str, &str); 45] = [
+    ("section-list", ""),
+    ("section-metrics", ""),
+    ("section-add", "<index>"),
+    ("section-delete", "<index>"),
+    ("section-duplicate", "<index>"),
+    ("section-move", "<index> <offset>"),
+    ("section-set", "<index> <length> <curve> <slope> <split>"),
+    ("pattern-list", "<section>"),
+    ("pattern-add", "<section>"),
+    ("pattern-delete", "<section> <index>"),
+    ("pattern-duplicate", "<section> <index>"),
+    ("pattern-adjust", "<section> <index>"),
+    ("pattern-copy-all", "<section> <source>"),
+    (
+        "pattern-set",
+        "<section> <index> <prop> <position> <size> <spacing> <x> <freq> <amp> <offset> <flags>",
+    ),
+    ("header-get", ""),
+    ("header-name-set", "<name>"),
+    ("header-background-set", "<background>"),
+    ("header-texture-set", "<texture>"),
+    ("header-flags-set", "<flags>"),
+    ("header-new-random-seed", ""),
+    ("color-list", ""),
+    ("color-set", "<index> <color>"),
+    ("project-list", ""),
+    ("project-file-name", ""),
+    ("project-new", "[#t|#f]"),
+    ("project-load", "<name> [#t|#f]"),
+    ("project-save", "[<name> [#t|#f]]"),
+    ("project-delete", "<name>"),
+    ("package-list", ""),
+    ("package-backgrounds", ""),
+    ("package-textures", ""),
+    ("package-props", ""),
+    ("package-load", "<name>"),
+    ("view-preview", ""),
+    ("view-preview-size", "<width> <height>"),
+    ("view-position", "<x> <z>"),
+    ("view-overview", "<#t|#f>"),
+    ("view-state-info", ""),
+    ("track-reverse", "<#t|#f>"),
+    ("track-play-test", ""),
+    ("undo", ""),
+    ("redo", ""),
+    ("exit", ""),
+    ("protocol-version", ""),
+    ("protocol-commands", ""),
+];
+
+//commands that only read state, and can be repeated or skipped without anyone noticing
+pub static QUERIES: [&str; 15] = [
+    "section-list",
+    "section-metrics",
+    "pattern-list",
+    "header-get",
+    "color-list",
+    "project-list",
+    "project-file-name",
+    "package-list",
+    "package-backgrounds",
+    "package-textures",
+    "package-props",
+    "view-preview",
+    "view-state-info",
+    "protocol-version",
+    "protocol-commands",
+];
+
+pub fn is_query(identifier: &str) -> bool { QUERIES.contains(&identifier) }
+
+//commands that overwrite something, so only the last of a run aimed at the same
+//target matters; the number is how many leading arguments name the target
+pub static SETTERS: [(&str, usize); 11] = [
+    ("section-set", 1),
+    ("pattern-set", 2),
+    ("header-name-set", 0),
+    ("header-background-set", 0),
+    ("header-texture-set", 0),
+    ("header-flags-set", 0),
+    ("color-set", 1),
+    ("view-preview-size", 0),
+    ("view-position", 0),
+    ("view-overview", 0),
+    ("track-reverse", 0),
+];
+
+pub fn setter_target(identifier: &str) -> Option<usize> {
+    SETTERS.iter().find(|(c, _)| *c == identifier).map(|(_, n)| *n)
+}
+
+//edits to the project, each of which the server keeps as one undo step
+pub fn is_undoable(identifier: &str) -> bool {
+    let prefixes = ["section-", "pattern-", "header-", "color-"];
+    let edit = prefixes.iter().any(|p| identifier.starts_with(p));
+    (edit && !is_query(identifier)) || identifier == "package-load"
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackCommand {
+    SectionList,
+    SectionMetrics,
+    SectionAdd { index: usize },
+    SectionDelete { index: usize },
+    SectionDuplicate { index: usize },
+    SectionMove { index: usize, offset: isize },
+    SectionSet { index: usize, length: i32, curve: i32, slope: i32, split: i32 },
+    PatternList { section: usize },
+    PatternAdd { section: usize },
+    PatternDelete { section: usize, index: usize },
+    PatternDuplicate { section: usize, index: usize },
+    PatternAdjust { section: usize, index: usize },
+    PatternCopyAll { section: usize, source: usize },
+    PatternSet { section: usize, index: usize, pattern: PatternValues },
+    HeaderGet,
+    HeaderNameSet { name: String },
+    HeaderBackgroundSet { background: String },
+    HeaderTextureSet { texture: String },
+    HeaderFlagsSet { flags: u32 },
+    HeaderNewRandomSeed,
+    ColorList,
+    ColorSet { index: usize, color: u32 },
+    ProjectList,
+    ProjectFileName,
+    ProjectNew { confirm: Option<bool> },
+    ProjectLoad { name: String, confirm: Option<bool> },
+    ProjectSave { name: Option<String>, confirm: Option<bool> },
+    ProjectDelete { name: String },
+    PackageList,
+    PackageBackgrounds,
+    PackageTextures,
+    PackageProps,
+    PackageLoad { name: String },
+    ViewPreview,
+    ViewPreviewSize { width: u32, height: u32 },
+    ViewPosition { x: i32, z: i32 },
+    ViewOverview { enabled: bool },
+    ViewStateInfo,
+    TrackReverse { enabled: bool },
+    TrackPlayTest,
+    Undo,
+    Redo,
+    Exit,
+    ProtocolVersion,
+    ProtocolCommands,
+    //anything else, passed through to the server untouched
+    Raw(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternValues {
+    pub prop: String,
+    pub position: i32,
+    pub size: i32,
+    pub spacing: i32,
+    pub x: i32,
+    pub freq: i32,
+    pub amp: i32,
+    pub offset: i32,
+    pub flags: u32,
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Empty,
+    Unknown(String),
+    Arity(&'static str, &'static str),
+    Argument(&'static str, String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Empty command."),
+            Self::Unknown(cmd) => write!(f, "Unknown command: {cmd}"),
+            Self::Arity(cmd, usage) => write!(f, "Wrong number of arguments. Usage: {cmd} {usage}"),
+            Self::Argument(cmd, arg) => write!(f, "Invalid argument for {cmd}: {arg}"),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+impl TrackCommand {
+    pub fn identifier(&self) -> &str {
+        match self {
+            Self::SectionList => "section-list",
+            Self::SectionMetrics => "section-metrics",
+            Self::SectionAdd { .. } => "section-add",
+            Self::SectionDelete { .. } => "section-delete",
+            Self::SectionDuplicate { .. } => "section-duplicate",
+            Self::SectionMove { .. } => "section-move",
+            Self::SectionSet { .. } => "section-set",
+            Self::PatternList { .. } => "pattern-list",
+            Self::PatternAdd { .. } => "pattern-add",
+            Self::PatternDelete { .. } => "pattern-delete",
+            Self::PatternDuplicate { .. } => "pattern-duplicate",
+            Self::PatternAdjust { .. } => "pattern-adjust",
+            Self::PatternCopyAll { .. } => "pattern-copy-all",
+            Self::PatternSet { .. } => "pattern-set",
+            Self::HeaderGet => "header-get",
+            Self::HeaderNameSet { .. } => "header-name-set",
+            Self::HeaderBackgroundSet { .. } => "header-background-set",
+            Self::HeaderTextureSet { .. } => "header-texture-set",
+            Self::HeaderFlagsSet { .. } => "header-flags-set",
+            Self::HeaderNewRandomSeed => "header-new-random-seed",
+            Self::ColorList => "color-list",
+            Self::ColorSet { .. } => "color-set",
+            Self::ProjectList => "project-list",
+            Self::ProjectFileName => "project-file-name",
+            Self::ProjectNew { .. } => "project-new",
+            Self::ProjectLoad { .. } => "project-load",
+            Self::ProjectSave { .. } => "project-save",
+            Self::ProjectDelete { .. } => "project-delete",
+            Self::PackageList => "package-list",
+            Self::PackageBackgrounds => "package-backgrounds",
+            Self::PackageTextures => "package-textures",
+            Self::PackageProps => "package-props",
+            Self::PackageLoad { .. } => "package-load",
+            Self::ViewPreview => "view-preview",
+            Self::ViewPreviewSize { .. } => "view-preview-size",
+            Self::ViewPosition { .. } => "view-position",
+            Self::ViewOverview { .. } => "view-overview",
+            Self::ViewStateInfo => "view-state-info",
+            Self::TrackReverse { .. } => "track-reverse",
+            Self::TrackPlayTest => "track-play-test",
+            Self::Undo => "undo",
+            Self::Redo => "redo",
+            Self::Exit => "exit",
+            Self::ProtocolVersion => "protocol-version",
+            Self::ProtocolCommands => "protocol-commands",
+            Self::Raw(line) => line.split_whitespace().next().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for TrackCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let confirm = |c: &Option<bool>| match c {
+            Some(c) => format!(" {}", utils::bool_string(*c)),
+            None => String::new(),
+        };
+        if let Self::Raw(line) = self {
+            return write!(f, "{}", line);
+        }
+        write!(f, "{}", self.identifier())?;
+        match self {
+            Self::SectionAdd { index }
+            | Self::SectionDelete { index }
+            | Self::SectionDuplicate { index } => write!(f, " {}", index),
+            Self::SectionMove { index, offset } => write!(f, " {} {}", index, offset),
+            Self::SectionSet {
+                index,
+                length,
+                curve,
+                slope,
+                split,
+            } => write!(f, " {} {} {} {} {}", index, length, curve, slope, split),
+            Self::PatternList { section } | Self::PatternAdd { section } => {
+                write!(f, " {}", section)
+            }
+            Self::PatternDelete { section, index }
+            | Self::PatternDuplicate { section, index }
+            | Self::PatternAdjust { section, index } => write!(f, " {} {}", section, index),
+            Self::PatternCopyAll { section, source } => write!(f, " {} {}", section, source),
+            Self::PatternSet {
+                section,
+                index,
+                pattern: p,
+            } => write!(
+                f,
+                " {} {} {} {} {} {} {} {} {} {} {}",
+                section,
+                index,
+                p.prop,
+                p.position,
+                p.size,
+                p.spacing,
+                p.x,
+                p.freq,
+                p.amp,
+                p.offset,
+                p.flags
+            ),
+            Self::HeaderNameSet { name: s }
+            | Self::HeaderBackgroundSet { background: s }
+            | Self::HeaderTextureSet { texture: s }
+            | Self::ProjectDelete { name: s }
+            | Self::PackageLoad { name: s } => write!(f, " {}", quote(s)),
+            Self::HeaderFlagsSet { flags } => write!(f, " {:#012b}", flags),
+            Self::ColorSet { index, color } => write!(f, " {} 0x{:x}", index, color),
+            Self::ProjectNew { confirm: c } => write!(f, "{}", confirm(c)),
+            Self::ProjectLoad { name, confirm: c } => write!(f, " {}{}", quote(name), confirm(c)),
+            Self::ProjectSave {
+                name: Some(name),
+                confirm: c,
+            } => write!(f, " {}{}", quote(name), confirm(c)),
+            Self::ViewPreviewSize { width, height } => write!(f, " {} {}", width, height),
+            Self::ViewPosition { x, z } => write!(f, " {} {}", x, z),
+            Self::ViewOverview { enabled } | Self::TrackReverse { enabled } => {
+                write!(f, " {}", utils::bool_string(*enabled))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+//walks over the arguments of a command line, checking them as it goes
+struct Arguments {
+    command: &'static str,
+    usage: &'static str,
+    args: Vec<String>,
+    next: usize,
+}
+
+impl Arguments {
+    fn string(&mut self) -> Result<String, CommandError> {
+        let arg = self
+            .args
+            .get(self.next)
+            .ok_or(CommandError::Arity(self.command, self.usage))?;
+        self.next += 1;
+        Ok(arg.clone())
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, CommandError> {
+        let arg = self.string()?;
+        arg.parse::<T>()
+            .map_err(|_| CommandError::Argument(self.command, arg))
+    }
+
+    fn radix(&mut self, prefix: &str, radix: u32) -> Result<u32, CommandError> {
+        let arg = self.string()?;
+        match arg.strip_prefix(prefix) {
+            Some(digits) => u32::from_str_radix(digits, radix),
+            None => arg.parse::<u32>(),
+        }
+        .map_err(|_| CommandError::Argument(self.command, arg))
+    }
+
+    fn boolean(&mut self) -> Result<bool, CommandError> {
+        match self.string()?.as_str() {
+            "#t" => Ok(true),
+            "#f" => Ok(false),
+            x => Err(CommandError::Argument(self.command, String::from(x))),
+        }
+    }
+
+    fn optional<T>(
+        &mut self, f: impl FnOnce(&mut Self) -> Result<T, CommandError>,
+    ) -> Result<Option<T>, CommandError> {
+        if self.next < self.args.len() {
+            f(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn finish(self, command: TrackCommand) -> Result<TrackCommand, CommandError> {
+        if self.next == self.args.len() {
+            Ok(command)
+        } else {
+            Err(CommandError::Arity(self.command, self.usage))
+        }
+    }
+}
+
+impl FromStr for TrackCommand {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = tokenize(s).into_iter();
+        let name = parts.next().ok_or(CommandError::Empty)?;
+        let (command, usage) = COMMANDS
+            .iter()
+            .find(|(c, _)| *c == name)
+            .ok_or(CommandError::Unknown(name))?;
+        let mut a = Arguments {
+            command,
+            usage,
+            args: parts.collect(),
+            next: 0,
+        };
+        let cmd = match *command {
+            "section-list" => Self::SectionList,
+            "section-metrics" => Self::SectionMetrics,
+            "section-add" => Self::SectionAdd { index: a.number()? },
+            "section-delete" => Self::SectionDelete { index: a.number()? },
+            "section-duplicate" => Self::SectionDuplicate { index: a.number()? },
+            "section-move" => Self::SectionMove {
+                index: a.number()?,
+                offset: a.number()?,
+            },
+            "section-set" => Self::SectionSet {
+                index: a.number()?,
+                length: a.number()?,
+                curve: a.number()?,
+                slope: a.number()?,
+                split: a.number()?,
+            },
+            "pattern-list" => Self::PatternList { section: a.number()? },
+            "pattern-add" => Self::PatternAdd { section: a.number()? },
+            "pattern-delete" => Self::PatternDelete {
+                section: a.number()?,
+                index: a.number()?,
+            },
+            "pattern-duplicate" => Self::PatternDuplicate {
+                section: a.number()?,
+                index: a.number()?,
+            },
+            "pattern-adjust" => Self::PatternAdjust {
+                section: a.number()?,
+                index: a.number()?,
+            },
+            "pattern-copy-all" => Self::PatternCopyAll {
+                section: a.number()?,
+                source: a.number()?,
+            },
+            "pattern-set" => Self::PatternSet {
+                section: a.number()?,
+                index: a.number()?,
+                pattern: PatternValues {
+                    prop: a.string()?,
+                    position: a.number()?,
+                    size: a.number()?,
+                    spacing: a.number()?,
+                    x: a.number()?,
+                    freq: a.number()?,
+                    amp: a.number()?,
+                    offset: a.number()?,
+                    flags: a.number()?,
+                },
+            },
+            "header-get" => Self::HeaderGet,
+            "header-name-set" => Self::HeaderNameSet { name: a.string()? },
+            "header-background-set" => Self::HeaderBackgroundSet {
+                background: a.string()?,
+            },
+            "header-texture-set" => Self::HeaderTextureSet { texture: a.string()? },
+            "header-flags-set" => Self::HeaderFlagsSet {
+                flags: a.radix("0b", 2)?,
+            },
+            "header-new-random-seed" => Self::HeaderNewRandomSeed,
+            "color-list" => Self::ColorList,
+            "color-set" => Self::ColorSet {
+                index: a.number()?,
+                color: a.radix("0x", 16)?,
+            },
+            "project-list" => Self::ProjectList,
+            "project-file-name" => Self::ProjectFileName,
+            "project-new" => Self::ProjectNew {
+                confirm: a.optional(Arguments::boolean)?,
+            },
+            "project-load" => Self::ProjectLoad {
+                name: a.string()?,
+                confirm: a.optional(Arguments::boolean)?,
+            },
+            "project-save" => Self::ProjectSave {
+                name: a.optional(Arguments::string)?,
+                confirm: a.optional(Arguments::boolean)?,
+            },
+            "project-delete" => Self::ProjectDelete { name: a.string()? },
+            "package-list" => Self::PackageList,
+            "package-backgrounds" => Self::PackageBackgrounds,
+            "package-textures" => Self::PackageTextures,
+            "package-props" => Self::PackageProps,
+            "package-load" => Self::PackageLoad { name: a.string()? },
+            "view-preview" => Self::ViewPreview,
+            "view-preview-size" => Self::ViewPreviewSize {
+                width: a.number()?,
+                height: a.number()?,
+            },
+            "view-position" => Self::ViewPosition {
+                x: a.number()?,
+                z: a.number()?,
+            },
+            "view-overview" => Self::ViewOverview { enabled: a.boolean()? },
+            "view-state-info" => Self::ViewStateInfo,
+            "track-reverse" => Self::TrackReverse { enabled: a.boolean()? },
+            "track-play-test" => Self::TrackPlayTest,
+            "undo" => Self::Undo,
+            "redo" => Self::Redo,
+            "exit" => Self::Exit,
+            "protocol-version" => Self::ProtocolVersion,
+            "protocol-commands" => Self::ProtocolCommands,
+            _ => unreachable!(),
+        };
+        a.finish(cmd)
+    }
+}
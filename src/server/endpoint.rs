@@ -0,0 +1,119 @@
+use std::{
+    fmt, io,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    time::Duration,
+};
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+//where the game is listening, kept unresolved so a restarted
+//test rig with a new address is found again on reconnect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp { host: String, port: u16 },
+    #[cfg(unix)]
+    Unix(PathBuf),
+    //not a server at all, but a recorded session played back
+    Replay(PathBuf),
+}
+
+impl Endpoint {
+    pub fn new(host: &str, port: u16) -> Self {
+        Endpoint::Tcp {
+            host: String::from(host.trim_start_matches('[').trim_end_matches(']')),
+            port,
+        }
+    }
+
+    //accepts "host", "host:port", "ipv6" and "[ipv6]:port"
+    pub fn parse(address: &str, default_port: u16) -> Result<Self, String> {
+        let invalid = || format!("Invalid address '{address}'");
+        let (host, port) = match address.rsplit_once(':') {
+            //a bare ipv6 address has colons but no brackets
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                (host, port.parse::<u16>().map_err(|_| invalid())?)
+            }
+            _ => (address, default_port),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Endpoint::new(host, port))
+    }
+
+    pub fn socket_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            Self::Tcp { host, port } => Ok((host.as_str(), *port).to_socket_addrs()?.collect()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{self} is not a network address"),
+            )),
+        }
+    }
+
+    //tries every address the host resolves to, without hanging on unreachable ones
+    pub fn connect_tcp(&self) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for address in self.socket_addrs()? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Could not resolve {self}"))
+        }))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { host, port } if host.contains(':') => write!(f, "[{host}]:{port}"),
+            Self::Tcp { host, port } => write!(f, "{host}:{port}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "{}", path.display()),
+            Self::Replay(path) => write!(f, "replay of {}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        let cases = [
+            ("localhost", Some(("localhost", 4000))),
+            ("localhost:33760", Some(("localhost", 33760))),
+            ("10.0.0.2:1", Some(("10.0.0.2", 1))),
+            ("::1", Some(("::1", 4000))),
+            ("[::1]", Some(("::1", 4000))),
+            ("[::1]:33760", Some(("::1", 33760))),
+            ("[fe80::1%eth0]:80", Some(("fe80::1%eth0", 80))),
+            ("localhost:", None),
+            ("localhost:port", None),
+            ("localhost:65536", None),
+            ("localhost:-1", None),
+            ("[::1]:", None),
+            (":33760", None),
+            ("", None),
+        ];
+        for (address, expected) in cases {
+            let expected = expected.map(|(host, port)| Endpoint::new(host, port));
+            assert_eq!(Endpoint::parse(address, 4000).ok(), expected, "{address:?}");
+        }
+    }
+
+    #[test]
+    fn display_reads_back() {
+        for address in ["localhost:33760", "[::1]:80", "10.0.0.2:1"] {
+            let endpoint = Endpoint::parse(address, 4000).unwrap();
+            assert_eq!(endpoint.to_string(), address);
+            assert_eq!(Endpoint::parse(&endpoint.to_string(), 4000), Ok(endpoint));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use super::queue::{needs_quotes, quote, tokenize};
+
+pub const EVENT_SIGNAL: &str = "<EVENT>";
+
+//something the server announces on its own, without being asked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    PlayTestFinished,
+    PackageReloaded(String),
+    ViewMoved { x: i32, z: i32 },
+    //another client of the same proxy changed something
+    RemoteChange(String),
+    //anything this editor doesn't know about yet, kept as sent
+    Unknown(String),
+}
+
+impl ServerEvent {
+    //None if the message isn't an event at all
+    pub fn parse(message: &str) -> Option<Self> {
+        let line = message.lines().next()?.trim().strip_prefix(EVENT_SIGNAL)?.trim();
+        //names with spaces come quoted, like in commands, or else run to the end
+        let words = tokenize(line);
+        let event = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["play-test-finished"] => Self::PlayTestFinished,
+            ["package-reloaded", ref name @ ..] if !name.is_empty() => {
+                Self::PackageReloaded(name.join(" "))
+            }
+            ["view-moved", x, z] => match (x.parse(), z.parse()) {
+                (Ok(x), Ok(z)) => Self::ViewMoved { x, z },
+                _ => Self::Unknown(String::from(line)),
+            },
+            ["remote-change", ..] => {
+                let command = line.split_once(char::is_whitespace).unwrap_or_default().1;
+                Self::RemoteChange(String::from(command.trim()))
+            }
+            _ => Self::Unknown(String::from(line)),
+        };
+        Some(event)
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::PlayTestFinished => "play-test-finished",
+            Self::PackageReloaded(_) => "package-reloaded",
+            Self::ViewMoved { .. } => "view-moved",
+            Self::RemoteChange(_) => "remote-change",
+            Self::Unknown(line) => line.split_whitespace().next().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PlayTestFinished => write!(f, "{}", self.name()),
+            Self::PackageReloaded(name) if needs_quotes(name) => {
+                write!(f, "{} {}", self.name(), quote(name))
+            }
+            Self::PackageReloaded(name) => write!(f, "{} {}", self.name(), name),
+            Self::ViewMoved { x, z } => write!(f, "{} {} {}", self.name(), x, z),
+            Self::RemoteChange(command) => write!(f, "{} {}", self.name(), command),
+            Self::Unknown(line) => write!(f, "{line}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn package_names_with_spaces() {
+        let reloaded = |name: &str| Some(ServerEvent::PackageReloaded(String::from(name)));
+        assert_eq!(ServerEvent::parse("<EVENT> package-reloaded city"), reloaded("city"));
+        assert_eq!(ServerEvent::parse("<EVENT> package-reloaded old city"), reloaded("old city"));
+        let quoted = "<EVENT> package-reloaded \"old city\"";
+        assert_eq!(ServerEvent::parse(quoted), reloaded("old city"));
+        let event = ServerEvent::PackageReloaded(String::from("say \"hi\""));
+        assert_eq!(ServerEvent::parse(&format!("{EVENT_SIGNAL} {event}")), Some(event));
+    }
+
+    #[test]
+    fn malformed_events_are_kept_as_sent() {
+        let unknown = |line: &str| Some(ServerEvent::Unknown(String::from(line)));
+        assert_eq!(ServerEvent::parse("<EVENT> package-reloaded"), unknown("package-reloaded"));
+        assert_eq!(ServerEvent::parse("<EVENT> view-moved 1 x"), unknown("view-moved 1 x"));
+        assert_eq!(ServerEvent::parse("monster>"), None);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+    mem,
+};
+
+use crate::utils::GenericResult;
+
+pub const MESSAGE_DELIMITER: u32 = 0xAAAAAAAA;
+//previews are the largest thing the server sends, and they're nowhere near this
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+const HEADER_SIZE: usize = 2 * mem::size_of::<u32>();
+const DELIMITER_BYTES: [u8; 4] = MESSAGE_DELIMITER.to_le_bytes();
+const READ_BUFFER_SIZE: usize = 8192;
+
+//wraps a message in the frame format used on both sides of the connection
+pub fn encode_message(msg: &str) -> Vec<u8> {
+    let mut message_bytes = Vec::with_capacity(msg.len() + HEADER_SIZE);
+    message_bytes.extend_from_slice(&DELIMITER_BYTES);
+    message_bytes.extend_from_slice(&(msg.len() as u32).to_le_bytes());
+    message_bytes.extend_from_slice(msg.as_bytes());
+    message_bytes
+}
+
+//something was wrong with the stream, but decoding can go on
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    Garbage(usize),
+    TooLarge(usize),
+    InvalidText(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Garbage(n) => write!(f, "Skipped {n} bytes looking for a message delimiter."),
+            Self::TooLarge(n) => write!(f, "Ignored a message header announcing {n} bytes."),
+            Self::InvalidText(n) => write!(f, "Dropped a {n} byte message that isn't valid UTF-8."),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+//turns an arbitrarily fragmented byte stream back into messages, scanning
+//ahead to the next delimiter whenever the stream stops making sense
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+    skipped: usize,
+}
+
+impl FrameDecoder {
+    pub fn new(max_frame_size: usize) -> Self {
+        FrameDecoder {
+            buffer: Vec::new(),
+            max_frame_size,
+            skipped: 0,
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) { self.buffer.extend_from_slice(bytes); }
+
+    //None means more bytes are needed before anything else can be decoded
+    pub fn next_frame(&mut self) -> Option<Result<String, FrameError>> {
+        match self.buffer.windows(DELIMITER_BYTES.len()).position(|w| w == DELIMITER_BYTES) {
+            Some(n) => {
+                //garbage is only reported once it's over, however it arrived
+                self.buffer.drain(..n);
+                let skipped = mem::take(&mut self.skipped) + n;
+                if skipped > 0 {
+                    return Some(Err(FrameError::Garbage(skipped)));
+                }
+            }
+            None => {
+                //the tail could be the start of a delimiter that hasn't fully arrived
+                let n = self.buffer.len().saturating_sub(DELIMITER_BYTES.len() - 1);
+                self.buffer.drain(..n);
+                self.skipped += n;
+                return None;
+            }
+        }
+        if self.buffer.len() < HEADER_SIZE {
+            return None;
+        }
+        let length = u32::from_le_bytes([
+            self.buffer[4],
+            self.buffer[5],
+            self.buffer[6],
+            self.buffer[7],
+        ]) as usize;
+        if length > self.max_frame_size {
+            //only step over the first byte, in case this "delimiter" was
+            //really the tail of a longer run of 0xAA bytes
+            self.buffer.drain(..1);
+            self.skipped += 1;
+            return Some(Err(FrameError::TooLarge(length)));
+        }
+        if self.buffer.len() < HEADER_SIZE + length {
+            return None;
+        }
+        let payload = self.buffer.drain(..HEADER_SIZE + length).skip(HEADER_SIZE).collect();
+        Some(String::from_utf8(payload).map_err(|_| FrameError::InvalidText(length)))
+    }
+}
+
+//blocking reads on top of a decoder, for threads that own one end of a connection
+pub struct FrameReader<R> {
+    inner: R,
+    decoder: FrameDecoder,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R, max_frame_size: usize) -> Self {
+        FrameReader {
+            inner,
+            decoder: FrameDecoder::new(max_frame_size),
+        }
+    }
+
+    //returns None when the other side closes the connection
+    pub fn read_frame(&mut self) -> GenericResult<Option<Result<String, FrameError>>> {
+        let mut chunk = [0; READ_BUFFER_SIZE];
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return Ok(Some(frame));
+            }
+            match self.inner.read(&mut chunk) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.decoder.push(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if connection_closed(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn connection_closed(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(e.kind(), UnexpectedEof | ConnectionReset | ConnectionAborted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn decode_all(decoder: &mut FrameDecoder) -> Vec<Result<String, FrameError>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    fn header(length: u32) -> Vec<u8> {
+        let mut bytes = DELIMITER_BYTES.to_vec();
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn frame_split_at_every_offset() {
+        let bytes = encode_message("section-list :: <OK>\nsection 0 250 0 0 0");
+        for split in 0..=bytes.len() {
+            let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+            decoder.push(&bytes[..split]);
+            let mut frames = decode_all(&mut decoder);
+            decoder.push(&bytes[split..]);
+            frames.extend(decode_all(&mut decoder));
+            assert_eq!(
+                frames,
+                vec![Ok(String::from("section-list :: <OK>\nsection 0 250 0 0 0"))],
+                "split at {split}"
+            );
+        }
+    }
+
+    #[test]
+    fn two_frames_in_one_read() {
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut bytes = encode_message("<READY>");
+        bytes.extend(encode_message("monster>"));
+        decoder.push(&bytes);
+        let frames = decode_all(&mut decoder);
+        assert_eq!(frames, vec![Ok(String::from("<READY>")), Ok(String::from("monster>"))]);
+    }
+
+    #[test]
+    fn garbage_before_delimiter_then_resync() {
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        decoder.push(b"noise");
+        assert_eq!(decode_all(&mut decoder), vec![]);
+        decoder.push(b"more noise");
+        decoder.push(&encode_message("monster>"));
+        let frames = decode_all(&mut decoder);
+        assert_eq!(frames, vec![Err(FrameError::Garbage(15)), Ok(String::from("monster>"))]);
+    }
+
+    #[test]
+    fn oversized_length_recovers_on_next_frame() {
+        let mut decoder = FrameDecoder::new(64);
+        let mut bytes = header(1000);
+        bytes.extend_from_slice(b"whatever the header promised");
+        bytes.extend(encode_message("monster>"));
+        decoder.push(&bytes);
+        let frames = decode_all(&mut decoder);
+        assert_eq!(frames.first(), Some(&Err(FrameError::TooLarge(1000))));
+        assert_eq!(frames.last(), Some(&Ok(String::from("monster>"))));
+        assert!(frames[1..frames.len() - 1].iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn invalid_utf8_is_dropped() {
+        let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+        let mut bytes = header(3);
+        bytes.extend_from_slice(&[0xff, 0xfe, 0xfd]);
+        bytes.extend(encode_message("monster>"));
+        decoder.push(&bytes);
+        let frames = decode_all(&mut decoder);
+        assert_eq!(frames, vec![Err(FrameError::InvalidText(3)), Ok(String::from("monster>"))]);
+    }
+
+    #[test]
+    fn truncated_frame_at_eof() {
+        let mut bytes = encode_message("<READY>");
+        let partial = encode_message("section-list :: <OK>");
+        bytes.extend_from_slice(&partial[..partial.len() - 4]);
+        let mut reader = FrameReader::new(Cursor::new(bytes), DEFAULT_MAX_FRAME_SIZE);
+        assert_eq!(reader.read_frame().unwrap(), Some(Ok(String::from("<READY>"))));
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+
+    //xorshift, so every run checks the same streams
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize { (self.next() % n as u64) as usize }
+
+        //mostly ascii, with some of everything else utf-8 can hold
+        fn message(&mut self) -> String {
+            let len = self.below(300);
+            (0..len)
+                .map(|_| match self.below(4) {
+                    0 => char::from_u32(self.below(0x11000) as u32).unwrap_or('?'),
+                    _ => char::from(b' ' + self.below(95) as u8),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn random_streams_round_trip() {
+        for seed in 1..=200 {
+            let mut random = Random(seed);
+            let messages = (0..random.below(20)).map(|_| random.message()).collect::<Vec<_>>();
+            let mut bytes = Vec::new();
+            let mut garbage = 0;
+            for msg in &messages {
+                //anything but the delimiter's byte can come between frames
+                if seed % 2 == 0 && random.below(3) == 0 {
+                    let noise = (0..1 + random.below(40)).map(|_| random.below(0xAA) as u8);
+                    let before = bytes.len();
+                    bytes.extend(noise);
+                    garbage += bytes.len() - before;
+                }
+                bytes.extend(encode_message(msg));
+            }
+            let mut decoder = FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE);
+            let mut frames = Vec::new();
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at(1 + random.below(rest.len()).min(64));
+                decoder.push(chunk);
+                frames.extend(decode_all(&mut decoder));
+                rest = tail;
+            }
+            let (decoded, errors): (Vec<_>, Vec<_>) = frames.into_iter().partition(Result::is_ok);
+            let decoded = decoded.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+            assert_eq!(decoded, messages, "seed {seed}");
+            let skipped = errors.into_iter().map(|e| match e {
+                Err(FrameError::Garbage(n)) => n,
+                e => panic!("seed {seed}: unexpected {e:?}"),
+            });
+            assert_eq!(skipped.sum::<usize>(), garbage, "seed {seed}");
+        }
+    }
+}
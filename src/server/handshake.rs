@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+use super::{commands::COMMANDS, Response, Ticket};
+
+//the protocol revision this editor was written against
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const VERSION_QUERY: &str = "protocol-version";
+pub const COMMANDS_QUERY: &str = "protocol-commands";
+
+//what the server said about itself right after connecting; servers that
+//predate the handshake are assumed to support everything, as before
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    version: Option<u32>,
+    commands: Option<HashSet<String>>,
+}
+
+impl Capabilities {
+    pub fn version(&self) -> Option<u32> { self.version }
+
+    pub fn supports(&self, command: &str) -> bool {
+        match &self.commands {
+            Some(commands) => commands.contains(command),
+            None => true,
+        }
+    }
+
+    //unlike supports(), only true if the server listed the command itself
+    pub fn advertises(&self, command: &str) -> bool {
+        self.commands.as_ref().is_some_and(|c| c.contains(command))
+    }
+
+    pub fn supports_all(&self, commands: &[&str]) -> bool {
+        commands.iter().all(|c| self.supports(c))
+    }
+
+    //commands known to the editor that the server doesn't have
+    pub fn missing(&self) -> Vec<&'static str> {
+        COMMANDS
+            .iter()
+            .map(|(c, _)| *c)
+            .filter(|c| !self.supports(c))
+            .collect()
+    }
+
+    //None if nothing the editor relies on is missing
+    pub fn incompatibility(&self) -> Option<String> {
+        let mut problems = Vec::new();
+        if let Some(v) = self.version.filter(|v| *v > PROTOCOL_VERSION) {
+            problems.push(format!(
+                "The server speaks protocol version {v}, but this editor only knows up to \
+                 version {PROTOCOL_VERSION}."
+            ));
+        }
+        let missing = self.missing();
+        if !missing.is_empty() {
+            problems.push(format!(
+                "The server doesn't support these commands, the features using them are \
+                 disabled: {}",
+                missing.join(", ")
+            ));
+        }
+        if problems.is_empty() {
+            None
+        } else {
+            Some(problems.join(" "))
+        }
+    }
+}
+
+//the two queries sent ahead of everything else on a new connection
+#[derive(Debug)]
+pub struct Handshake {
+    version_ticket: Ticket,
+    commands_ticket: Ticket,
+    answered: usize,
+    capabilities: Capabilities,
+}
+
+impl Handshake {
+    pub fn new(version_ticket: Ticket, commands_ticket: Ticket) -> Self {
+        Handshake {
+            version_ticket,
+            commands_ticket,
+            answered: 0,
+            capabilities: Capabilities::default(),
+        }
+    }
+
+    pub fn owns(&self, ticket: Ticket) -> bool {
+        ticket == self.version_ticket || ticket == self.commands_ticket
+    }
+
+    //an error only means the server is older than the handshake
+    pub fn receive(&mut self, ticket: Ticket, response: &Response) {
+        self.answered += 1;
+        let Response::Success(_, result) = response else { return };
+        if ticket == self.version_ticket {
+            self.capabilities.version = result.trim().parse().ok();
+        } else if ticket == self.commands_ticket {
+            let commands = result.lines().map(str::trim).filter(|c| !c.is_empty());
+            self.capabilities.commands = Some(commands.map(String::from).collect());
+        }
+    }
+
+    pub fn finished(&self) -> bool { self.answered == 2 }
+
+    pub fn capabilities(self) -> Capabilities { self.capabilities }
+}
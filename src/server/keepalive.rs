@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use super::handshake::VERSION_QUERY;
+
+pub const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+//read-only and answered by any server, even those that reject it as unknown
+pub const HEARTBEAT_COMMAND: &str = VERSION_QUERY;
+
+//how the queue tells a hung server apart from a quiet one
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    //how long the connection may sit idle at the prompt before
+    //it's poked with a no-op command; None disables the heartbeat
+    pub heartbeat: Option<Duration>,
+    //how long a command may go unanswered before the server is
+    //considered unresponsive
+    pub response_timeout: Duration,
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            heartbeat: None,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::utils::UnitResult;
+
+//what one kind of command has cost so far; bytes count message payloads only
+#[derive(Debug, Clone, Default)]
+pub struct CommandStats {
+    pub count: u64,
+    pub errors: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl CommandStats {
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            n => self.total / n as u32,
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            n => self.errors as f64 / n as f64,
+        }
+    }
+}
+
+//latency from handing a command to the server until its response arrives,
+//kept per command identifier for the whole session
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands: BTreeMap<String, CommandStats>,
+    since: Option<Instant>,
+}
+
+impl Metrics {
+    pub fn record(
+        &mut self, identifier: &str, latency: Duration, sent: usize, received: usize, error: bool,
+    ) {
+        self.since.get_or_insert_with(Instant::now);
+        let stats = self.commands.entry(String::from(identifier)).or_default();
+        if stats.count == 0 || latency < stats.min {
+            stats.min = latency;
+        }
+        stats.max = stats.max.max(latency);
+        stats.count += 1;
+        stats.errors += error as u64;
+        stats.total += latency;
+        stats.bytes_sent += sent as u64;
+        stats.bytes_received += received as u64;
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = (&str, &CommandStats)> {
+        self.commands.iter().map(|(id, s)| (id.as_str(), s))
+    }
+
+    pub fn total(&self) -> u64 { self.commands.values().map(|s| s.count).sum() }
+
+    //commands answered per second since the first one was
+    pub fn throughput(&self) -> f64 {
+        match self.since.map(|t| t.elapsed().as_secs_f64()) {
+            Some(secs) if secs > 0.0 => self.total() as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    //a table for printing, slowest commands first
+    pub fn report(&self) -> String {
+        if self.commands.is_empty() {
+            return String::from("No commands answered yet.");
+        }
+        let mut rows = self.commands().collect::<Vec<_>>();
+        rows.sort_by_key(|(_, s)| Reverse(s.mean()));
+        let mut s = format!(
+            "{:24} {:>7} {:>7} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            "command", "count", "errors", "mean", "min", "max", "sent", "received"
+        );
+        for (id, stats) in rows {
+            s.push_str(&format!(
+                "{:24} {:>7} {:>6.1}% {:>10.2?} {:>10.2?} {:>10.2?} {:>10} {:>10}\n",
+                id,
+                stats.count,
+                stats.error_rate() * 100.0,
+                stats.mean(),
+                stats.min,
+                stats.max,
+                stats.bytes_sent,
+                stats.bytes_received
+            ));
+        }
+        s.push_str(&format!("{} commands, {:.1}/s", self.total(), self.throughput()));
+        s
+    }
+
+    pub fn save_csv(&self, path: &Path) -> UnitResult {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "command,count,errors,mean_ms,min_ms,max_ms,bytes_sent,bytes_received")?;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        for (id, stats) in self.commands() {
+            writeln!(
+                file,
+                "{},{},{},{:.3},{:.3},{:.3},{},{}",
+                id,
+                stats.count,
+                stats.errors,
+                ms(stats.mean()),
+                ms(stats.min),
+                ms(stats.max),
+                stats.bytes_sent,
+                stats.bytes_received
+            )?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
@@ -1,126 +1,198 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::{
     collections::VecDeque,
-    io::{Read, Write},
-    mem,
-    net::{Shutdown, SocketAddr, TcpStream},
-    sync::mpsc::{self, Receiver, Sender},
+    net::TcpStream,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
     thread::{self, JoinHandle},
     time::Duration,
 };
 
-use crate::utils::UnitResult;
+use crate::utils::{GenericResult, UnitResult};
 
+mod batch;
+pub mod commands;
+mod endpoint;
+mod event;
+mod frame;
+mod handshake;
+mod keepalive;
+mod metrics;
+pub mod parser;
+mod process;
 mod queue;
+pub mod record;
+mod reconnect;
+mod transport;
 
-pub use queue::{CommandQueue, Response};
+pub use batch::{BATCH_BEGIN, BATCH_END};
+pub use endpoint::{Endpoint, DEFAULT_HOST};
+pub use event::{ServerEvent, EVENT_SIGNAL};
+pub use frame::{encode_message, FrameDecoder, FrameError, FrameReader, DEFAULT_MAX_FRAME_SIZE};
+pub use handshake::PROTOCOL_VERSION;
+pub use keepalive::KeepAlive;
+pub use process::ServerProcess;
+pub use queue::{quote, tokenize, CallError, CommandQueue, Response, Ticket};
+use record::{Direction, Recorder, Replay};
+pub use reconnect::ReconnectPolicy;
+pub use transport::Transport;
 
-const MESSAGE_DELIMITER: u32 = 0xAAAAAAAA;
-const PROMPT_MESSAGE: &str = "monster>";
+pub const PROMPT_MESSAGE: &str = "monster>";
 
 enum Message {
     Send(String),
-    Receive,
     Terminate,
 }
 
+enum Incoming {
+    Message(String),
+    Malformed(FrameError),
+}
+
+//called from the reader thread whenever a message arrives
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
+
+//things the connection threads call into, set up once by the queue's owner
+#[derive(Default, Clone)]
+pub struct Hooks {
+    pub waker: Option<Waker>,
+    pub recorder: Option<Arc<Recorder>>,
+    //frames announcing more than this are dropped, DEFAULT_MAX_FRAME_SIZE if unset
+    pub max_frame_size: Option<usize>,
+}
+
+//a stream to the server that no queue has taken over yet, so the slow part
+//of connecting can be done on another thread
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    Replay(Replay),
+}
+
+impl Connection {
+    pub fn open(endpoint: &Endpoint) -> GenericResult<Self> {
+        match endpoint {
+            Endpoint::Tcp { .. } => {
+                let stream = endpoint.connect_tcp()?;
+                stream.set_nodelay(true)?;
+                Ok(Connection::Tcp(stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Connection::Unix(UnixStream::connect(path)?)),
+            Endpoint::Replay(path) => Ok(Connection::Replay(Replay::open(path)?)),
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct ServerHandle {
     pub connected: bool,
-    server_thread: Option<JoinHandle<UnitResult>>,
-    receiver: Option<Receiver<String>>,
+    stream: Option<Box<dyn Transport>>,
+    reader_thread: Option<JoinHandle<UnitResult>>,
+    writer_thread: Option<JoinHandle<UnitResult>>,
+    receiver: Option<Receiver<Incoming>>,
     sender: Option<Sender<Message>>,
     responses: VecDeque<String>,
+    diagnostics: VecDeque<FrameError>,
 }
 
 impl ServerHandle {
-    pub fn new() -> Self {
-        ServerHandle {
-            connected: false,
-            server_thread: None,
-            receiver: None,
-            sender: None,
-            responses: VecDeque::new(),
+    pub fn new() -> Self { Default::default() }
+
+    pub fn connect(
+        &mut self, endpoint: &Endpoint, connection: Connection, hooks: Hooks,
+    ) -> UnitResult {
+        if let Some(recorder) = &hooks.recorder {
+            recorder.note(&format!("connecting to {endpoint}"));
+        }
+        match connection {
+            Connection::Tcp(stream) => self.open(stream, hooks),
+            #[cfg(unix)]
+            Connection::Unix(stream) => self.open(stream, hooks),
+            Connection::Replay(replay) => self.open(replay, hooks),
         }
     }
 
-    pub fn connect(&mut self, address: &SocketAddr) -> UnitResult {
-        let stream = TcpStream::connect(address)?;
-        let (mut reader, mut writer) = (stream.try_clone()?, stream.try_clone()?);
+    fn open<T: Transport>(&mut self, stream: T, hooks: Hooks) -> UnitResult {
+        let (reader, mut writer) = (stream.try_clone()?, stream.try_clone()?);
+        let Hooks {
+            waker,
+            recorder,
+            max_frame_size,
+        } = hooks;
+        let max_frame_size = max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE);
+        let writer_recorder = recorder.clone();
         let (cs, cr) = mpsc::channel(); //controller
         let (ms, mr) = mpsc::channel(); //message
-        let st = thread::spawn(move || {
-            'main: loop {
-                match cr.try_recv() {
-                    Err(mpsc::TryRecvError::Disconnected) => {
-                        return Err("Disconnected from server.".into());
-                    }
-                    Err(mpsc::TryRecvError::Empty) => {
-                        thread::sleep(Duration::from_millis(10));
+        //the reader blocks on the socket and hands every frame over as it arrives
+        let rt = thread::spawn(move || {
+            let mut reader = FrameReader::new(reader, max_frame_size);
+            while let Some(frame) = reader.read_frame()? {
+                if let Some(recorder) = &recorder {
+                    match &frame {
+                        Ok(msg) => recorder.frame(Direction::Incoming, msg),
+                        Err(e) => recorder.note(&e.to_string()),
                     }
-                    Ok(msg) => match msg {
-                        Message::Terminate => {
-                            break;
-                        }
-                        Message::Send(msg) => {
-                            let mut message_bytes = vec![];
-                            message_bytes.write_all(&MESSAGE_DELIMITER.to_le_bytes())?;
-                            message_bytes.write_all(&(msg.len() as u32).to_le_bytes())?;
-                            message_bytes.write_all(msg.as_bytes())?;
-                            writer.write_all(&message_bytes)?;
-                            writer.flush()?;
-                        }
-                        Message::Receive => {
-                            'recv: loop {
-                                thread::sleep(Duration::from_millis(1));
-                                let mut peek_buffer = [0; 8];
-                                let n = reader.peek(&mut peek_buffer)?;
-                                if n == peek_buffer.len() {
-                                    //consume the first 8 bytes from the stream
-                                    reader.read_exact(&mut peek_buffer)?;
-                                    let (header, length) =
-                                        peek_buffer.split_at(mem::size_of::<u32>());
-                                    if u32::from_le_bytes(header.try_into()?) == MESSAGE_DELIMITER {
-                                        let message_size =
-                                            u32::from_le_bytes(length.try_into()?) as usize;
-                                        let mut read_buffer = vec![0; message_size];
-                                        reader.read_exact(&mut read_buffer)?;
-                                        let msg = String::from_utf8(read_buffer)?;
-                                        let exit = msg.starts_with(PROMPT_MESSAGE);
-                                        ms.send(msg)?;
-                                        if exit {
-                                            break 'recv;
-                                        }
-                                    }
-                                } else if n == 0 {
-                                    //a read of zero bytes means the connection was lost
-                                    break 'main;
-                                }
-                            }
+                }
+                let msg = match frame {
+                    Ok(msg) => Incoming::Message(msg),
+                    Err(e) => Incoming::Malformed(e),
+                };
+                if ms.send(msg).is_err() {
+                    break;
+                }
+                if let Some(wake) = &waker {
+                    wake();
+                }
+            }
+            Ok(())
+        });
+        //the writer blocks on the channel, and never waits for the reader;
+        //deciding when the server is ready for a command is up to the queue
+        let wt = thread::spawn(move || {
+            for msg in cr {
+                match msg {
+                    Message::Terminate => break,
+                    Message::Send(msg) => {
+                        if let Some(recorder) = &writer_recorder {
+                            recorder.frame(Direction::Outgoing, &msg);
                         }
-                    },
+                        writer.write_all(&encode_message(&msg))?;
+                        writer.flush()?;
+                    }
                 }
             }
-            writer.shutdown(Shutdown::Both)?;
+            //fails if the server already closed its end, which is fine
+            let _ = writer.close();
             Ok(())
         });
-        cs.send(Message::Receive)?;
+        self.stream = Some(Box::new(stream));
         self.sender = Some(cs);
         self.receiver = Some(mr);
-        self.server_thread = Some(st);
+        self.reader_thread = Some(rt);
+        self.writer_thread = Some(wt);
         self.connected = true;
         Ok(())
     }
 
     pub fn disconnect(self) -> UnitResult {
         if self.connected {
-            if let (Some(sender), Some(thread)) = (self.sender, self.server_thread) {
-                sender.send(Message::Terminate)?;
-                match thread.join() {
-                    Ok(r) => {
+            if let (Some(stream), Some(sender), Some(reader), Some(writer)) =
+                (self.stream, self.sender, self.reader_thread, self.writer_thread)
+            {
+                //closing the socket wakes up both threads, wherever they are blocked
+                let _ = sender.send(Message::Terminate);
+                let _ = stream.close();
+                match (reader.join(), writer.join()) {
+                    (Ok(r), Ok(w)) => {
                         println!("Disconnected successfully.");
-                        r
+                        w.and(r)
                     }
-                    Err(_) => Err("Failed to join server thread.".into()),
+                    _ => Err("Failed to join server thread.".into()),
                 }
             } else {
                 Err("Failed to access server thread.".into())
@@ -133,13 +205,8 @@ impl ServerHandle {
     pub fn send(&mut self, message: &str) -> UnitResult {
         if self.connected {
             if let Some(sender) = &mut self.sender {
-                let s = sender.send(Message::Send(String::from(message)));
-                let r = sender.send(Message::Receive);
-                match (s, r) {
-                    (Ok(_), Ok(_)) => Ok(()),
-                    (Err(e), _) => Err(e.into()),
-                    (_, Err(e)) => Err(e.into()),
-                }
+                sender.send(Message::Send(String::from(message)))?;
+                Ok(())
             } else {
                 unreachable!()
             }
@@ -151,21 +218,54 @@ impl ServerHandle {
     pub fn update(&mut self) -> UnitResult {
         if self.connected {
             if let Some(recv) = &self.receiver {
-                match recv.try_recv() {
-                    Err(mpsc::TryRecvError::Disconnected) => {
-                        return Err("Disconnected from server.".into());
-                    }
-                    Ok(msg) => {
-                        self.responses.push_back(msg);
+                loop {
+                    match recv.try_recv() {
+                        Ok(msg) => Self::store(msg, &mut self.responses, &mut self.diagnostics),
+                        Err(mpsc::TryRecvError::Empty) => break,
+                        //hand over whatever came before the end, like an <EXIT>,
+                        //and report the disconnection on the next update
+                        Err(mpsc::TryRecvError::Disconnected) if !self.responses.is_empty() => {
+                            break
+                        }
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            return Err("Disconnected from server.".into());
+                        }
                     }
-                    _ => {}
                 }
             }
         }
         Ok(())
     }
 
+    //blocks until a message arrives or the timeout runs out
+    pub fn wait(&mut self, timeout: Duration) -> UnitResult {
+        if self.connected && self.responses.is_empty() {
+            if let Some(recv) = &self.receiver {
+                match recv.recv_timeout(timeout) {
+                    Ok(msg) => Self::store(msg, &mut self.responses, &mut self.diagnostics),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        return Err("Disconnected from server.".into());
+                    }
+                }
+            }
+        }
+        self.update()
+    }
+
+    fn store(
+        msg: Incoming, responses: &mut VecDeque<String>, diagnostics: &mut VecDeque<FrameError>,
+    ) {
+        match msg {
+            Incoming::Message(msg) => responses.push_back(msg),
+            Incoming::Malformed(e) => diagnostics.push_back(e),
+        }
+    }
+
     pub fn receive(&mut self) -> Option<String> {
         self.responses.pop_front()
     }
+
+    //problems found in the byte stream that didn't cost us the connection
+    pub fn diagnostic(&mut self) -> Option<FrameError> { self.diagnostics.pop_front() }
 }
@@ -0,0 +1,234 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use super::Response;
+
+pub const EMPTY_SIGNAL: &str = "<EMPTY>";
+pub const PALETTE_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub struct ParseError {
+    command: String,
+    line: String,
+    reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to parse response to '{}' at line '{}': {}",
+            self.command, self.line, self.reason
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+//types that can be read from the result of a successful response
+pub trait FromResponse: Sized {
+    fn from_response(response: &Response) -> Result<Self, ParseError>;
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SectionRecord {
+    pub length: i32,
+    pub curve: i32,
+    pub slope: i32,
+    pub split: i32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PatternRecord {
+    pub prop: String,
+    pub position: i32,
+    pub size: i32,
+    pub spacing: i32,
+    pub x: i32,
+    pub freq: i32,
+    pub amp: i32,
+    pub offset: i32,
+    pub flags: u32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HeaderRecord {
+    pub package: String,
+    pub name: String,
+    pub background: String,
+    pub texture: String,
+    pub random_seed: i64,
+    pub flags: u32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 4]; PALETTE_SIZE]);
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ViewState {
+    pub x: i32,
+    pub z: i32,
+    pub reverse: bool,
+    pub overview: bool,
+}
+
+//reads whitespace separated fields off a single line
+struct Fields<'a> {
+    parts: std::str::SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: &'a str) -> Self {
+        Fields {
+            parts: line.split_whitespace(),
+        }
+    }
+
+    fn skip(mut self, n: usize) -> Self {
+        for _ in 0..n {
+            self.parts.next();
+        }
+        self
+    }
+
+    fn text(&mut self, name: &str) -> Result<&'a str, String> {
+        self.parts.next().ok_or(format!("missing field '{name}'"))
+    }
+
+    fn number<T: FromStr>(&mut self, name: &str) -> Result<T, String> {
+        let value = self.text(name)?;
+        value
+            .parse::<T>()
+            .map_err(|_| format!("invalid value '{value}' for field '{name}'"))
+    }
+}
+
+fn error(response: &Response, line: &str, reason: String) -> ParseError {
+    ParseError {
+        command: String::from(response.header()),
+        line: String::from(line),
+        reason,
+    }
+}
+
+//one record per line, with an empty list signaled by <EMPTY>
+fn parse_lines<T>(
+    response: &Response, mut f: impl FnMut(&str) -> Result<T, String>,
+) -> Result<Vec<T>, ParseError> {
+    let result = response.result();
+    if result.trim().starts_with(EMPTY_SIGNAL) {
+        return Ok(Vec::new());
+    }
+    result
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| f(line).map_err(|reason| error(response, line, reason)))
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "#t" => Ok(true),
+        "#f" => Ok(false),
+        x => Err(format!("invalid boolean '{x}'")),
+    }
+}
+
+//plain lists of names, like projects or package contents
+pub fn names(response: &Response) -> Vec<String> {
+    //a line is always a valid name, so this never fails
+    parse_lines(response, |line| Ok(String::from(line))).unwrap_or_default()
+}
+
+impl FromResponse for Vec<SectionRecord> {
+    fn from_response(response: &Response) -> Result<Self, ParseError> {
+        parse_lines(response, |line| {
+            let mut f = Fields::new(line).skip(2);
+            Ok(SectionRecord {
+                length: f.number("length")?,
+                curve: f.number("curve")?,
+                slope: f.number("slope")?,
+                split: f.number("split")?,
+            })
+        })
+    }
+}
+
+impl FromResponse for Vec<PatternRecord> {
+    fn from_response(response: &Response) -> Result<Self, ParseError> {
+        parse_lines(response, |line| {
+            let mut f = Fields::new(line).skip(3);
+            Ok(PatternRecord {
+                prop: String::from(f.text("prop")?),
+                position: f.number("position")?,
+                size: f.number("size")?,
+                spacing: f.number("spacing")?,
+                x: f.number("x")?,
+                freq: f.number("freq")?,
+                amp: f.number("amp")?,
+                offset: f.number("offset")?,
+                flags: f.number("flags")?,
+            })
+        })
+    }
+}
+
+impl FromResponse for HeaderRecord {
+    fn from_response(response: &Response) -> Result<Self, ParseError> {
+        let mut header = HeaderRecord::default();
+        parse_lines(response, |line| {
+            //fields may be present with an empty value, like an unnamed track
+            let (field, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            match field {
+                "package" => header.package = String::from(value),
+                "name" => header.name = String::from(value),
+                "background" => header.background = String::from(value),
+                "texture" => header.texture = String::from(value),
+                "random-seed" => header.random_seed = Fields::new(value).number(field)?,
+                "flags" => header.flags = Fields::new(value).number(field)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(header)
+    }
+}
+
+impl FromResponse for Palette {
+    fn from_response(response: &Response) -> Result<Self, ParseError> {
+        let colors = parse_lines(response, |line| {
+            u32::from_str_radix(line, 16)
+                .map(u32::to_be_bytes)
+                .map_err(|_| format!("invalid color '{line}'"))
+        })?;
+        let count = colors.len();
+        colors.try_into().map(Palette).map_err(|_| {
+            error(
+                response,
+                response.result(),
+                format!("expected {PALETTE_SIZE} colors, found {count}"),
+            )
+        })
+    }
+}
+
+impl FromResponse for ViewState {
+    fn from_response(response: &Response) -> Result<Self, ParseError> {
+        let mut view = ViewState::default();
+        parse_lines(response, |line| {
+            let mut f = Fields::new(line);
+            match f.text("field")? {
+                "position" => {
+                    view.x = f.number("x")?;
+                    view.z = f.number("z")?;
+                }
+                "reverse" => view.reverse = parse_bool(f.text("reverse")?)?,
+                "overview" => view.overview = parse_bool(f.text("overview")?)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(view)
+    }
+}
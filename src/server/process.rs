@@ -0,0 +1,174 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{Endpoint, ReconnectPolicy};
+use crate::utils::{GenericResult, UnitResult};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const STARTUP_POLL_TIME: Duration = Duration::from_millis(100);
+//a server that stays up this long has recovered, and gets its restarts back
+const STABLE_TIME: Duration = Duration::from_secs(30);
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTARTS: u32 = 5;
+
+//the game server, started and watched over by the editor instead of by hand
+pub struct ServerProcess {
+    command: Vec<String>,
+    restart: bool,
+    child: Option<Child>,
+    started: Instant,
+    restarts: ReconnectPolicy,
+    restart_pending: bool,
+    sender: Sender<String>,
+    receiver: Receiver<String>,
+}
+
+impl ServerProcess {
+    //command is the program followed by its arguments
+    pub fn spawn(command: Vec<String>, restart: bool) -> GenericResult<Self> {
+        if command.is_empty() {
+            return Err("The server command is empty.".into());
+        }
+        let (sender, receiver) = mpsc::channel();
+        let mut process = ServerProcess {
+            command,
+            restart,
+            child: None,
+            started: Instant::now(),
+            restarts: ReconnectPolicy::new(RESTART_DELAY, STARTUP_TIMEOUT, MAX_RESTARTS),
+            restart_pending: false,
+            sender,
+            receiver,
+        };
+        process.start()?;
+        Ok(process)
+    }
+
+    fn start(&mut self) -> UnitResult {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start '{}': {e}", self.command[0]))?;
+        if let Some(stdout) = child.stdout.take() {
+            forward_lines(stdout, self.sender.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward_lines(stderr, self.sender.clone());
+        }
+        self.child = Some(child);
+        self.started = Instant::now();
+        Ok(())
+    }
+
+    //blocks until the server accepts connections, or gives up if it exits first
+    pub fn wait_until_ready(&mut self, endpoint: &Endpoint) -> UnitResult {
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            if let Some(status) = self.exit_status()? {
+                return Err(format!("The server exited before accepting connections ({status}).")
+                    .into());
+            }
+            //the probe connection is dropped right away, like a client giving up
+            let accepting = match endpoint {
+                Endpoint::Tcp { .. } => endpoint.connect_tcp().is_ok(),
+                #[cfg(unix)]
+                Endpoint::Unix(path) => UnixStream::connect(path).is_ok(),
+                Endpoint::Replay(_) => return Err("A replay has no server to start.".into()),
+            };
+            if accepting {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(format!("The server isn't accepting connections on {endpoint}.").into());
+            }
+            thread::sleep(STARTUP_POLL_TIME);
+        }
+    }
+
+    fn exit_status(&mut self) -> GenericResult<Option<ExitStatus>> {
+        match &mut self.child {
+            Some(child) => Ok(child.try_wait()?),
+            None => Ok(None),
+        }
+    }
+
+    //the next line of output or news about the process, restarting it if it crashed;
+    //meant to be called regularly by whoever shows the output
+    pub fn message(&mut self) -> Option<String> {
+        match self.exit_status() {
+            Ok(Some(status)) => {
+                self.child = None;
+                if self.started.elapsed() >= STABLE_TIME {
+                    self.restarts.reset();
+                }
+                let crashed = !status.success();
+                self.restart_pending = crashed && self.restart && !self.restarts.exhausted();
+                let msg = if self.restart_pending {
+                    self.restarts.failed();
+                    "restarting it"
+                } else {
+                    "not restarting it"
+                };
+                self.notify(format!("Server process exited ({status}), {msg}."));
+            }
+            Ok(None) => {}
+            Err(e) => self.notify(format!("Lost track of the server process: {e}")),
+        }
+        if self.restart_pending && self.restarts.due() {
+            self.restart_pending = false;
+            let attempt = self.restarts.attempts();
+            match self.start() {
+                Ok(()) => self.notify(format!("Server process restarted (attempt {attempt}).")),
+                Err(e) => {
+                    self.notify(e.to_string());
+                    self.restarts.failed();
+                    self.restart_pending = !self.restarts.exhausted();
+                }
+            }
+        }
+        self.receiver.try_recv().ok()
+    }
+
+    fn notify(&self, msg: String) {
+        //the receiver lives as long as self, so this can't fail
+        let _ = self.sender.send(msg);
+    }
+
+    pub fn stop(&mut self) -> UnitResult {
+        if let Some(mut child) = self.child.take() {
+            child.kill()?;
+            child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ServerProcess {
+    //never leave a game running behind the editor's back
+    fn drop(&mut self) {
+        if let Err(e) = self.stop() {
+            eprintln!("[ERROR] Failed to stop the server process: {e}");
+        }
+    }
+}
+
+fn forward_lines(stream: impl Read + Send + 'static, sender: Sender<String>) {
+    thread::spawn(move || {
+        //stops when the process closes the pipe, or nobody is listening anymore
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
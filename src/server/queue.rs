@@ -1,9 +1,21 @@
 use std::{
-    collections::VecDeque,
-    net::SocketAddr,
+    collections::{HashSet, VecDeque},
+    error::Error,
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use super::ServerHandle;
+use super::{
+    batch::{Batch, UndoGroups, BATCH_BEGIN, BATCH_END, REDO, UNDO},
+    commands,
+    handshake::{Capabilities, Handshake, COMMANDS_QUERY, VERSION_QUERY},
+    keepalive::{KeepAlive, HEARTBEAT_COMMAND},
+    metrics::Metrics,
+    record::Recorder,
+    Connection, Endpoint, Hooks, ServerEvent, ServerHandle,
+};
 use crate::utils::UnitResult;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -19,12 +31,17 @@ impl Response {
         (err, self.identifier(), self.args(), self.result())
     }
 
+    //the command line as echoed back by the server
+    pub fn header(&self) -> &str {
+        match self {
+            Self::Success(c, _) => &c[..],
+            Self::Error(c, _) => &c[..],
+            Self::Nothing => "",
+        }
+    }
+
     pub fn identifier(&self) -> &str {
-        let (c, _) = match self {
-            Self::Success(c, r) => (&c[..], &r[..]),
-            Self::Error(c, r) => (&c[..], &r[..]),
-            Self::Nothing => ("", ""),
-        };
+        let c = self.header();
         match c.split_once(char::is_whitespace) {
             Some((id, _)) => id,
             None => c,
@@ -32,11 +49,7 @@ impl Response {
     }
 
     pub fn args(&self) -> &str {
-        let (c, _) = match self {
-            Self::Success(c, r) => (&c[..], &r[..]),
-            Self::Error(c, r) => (&c[..], &r[..]),
-            Self::Nothing => ("", ""),
-        };
+        let c = self.header();
         c.split_once(char::is_whitespace).unwrap_or_default().1
     }
 
@@ -47,16 +60,77 @@ impl Response {
             Self::Nothing => "",
         }
     }
+
+    //the response as the server would have sent it
+    pub fn to_message(&self) -> String {
+        let signal = match self {
+            Self::Success(_, _) => "<OK>",
+            Self::Error(_, _) => "<ERROR>",
+            Self::Nothing => return String::new(),
+        };
+        match self.result() {
+            "" => format!("{} :: {}", self.header(), signal),
+            result => format!("{} :: {}\n{}", self.header(), signal, result),
+        }
+    }
+}
+
+//identifies a single request sent through the queue, so its response can be
+//told apart from responses to other requests with the same command
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ticket(u64);
+
+impl Ticket {
+    //assigned to responses that could not be matched to any request
+    pub const UNSOLICITED: Ticket = Ticket(0);
+
+    //advances a ticket counter, returning the new ticket
+    fn next(&mut self) -> Ticket {
+        self.0 += 1;
+        *self
+    }
 }
 
 #[derive(Debug)]
+pub enum CallError {
+    Timeout,
+    Disconnected(String),
+    Server(Response),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "Timed out waiting for a response."),
+            Self::Disconnected(msg) => write!(f, "{msg}"),
+            Self::Server(r) => write!(f, "{}", r.result()),
+        }
+    }
+}
+
+impl Error for CallError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Argument {
+    value: String,
+    quoted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Command {
     command: String,
-    args: Vec<String>,
+    args: Vec<Argument>,
 }
 
 impl From<&str> for Command {
-    fn from(value: &str) -> Self { Command::from(String::from(value)) }
+    fn from(value: &str) -> Self {
+        let mut parts = split_arguments(value).into_iter();
+        let command = parts.next().map(|x| x.value).unwrap_or_default();
+        Command {
+            command,
+            args: parts.collect(),
+        }
+    }
 }
 
 impl From<Command> for String {
@@ -65,53 +139,193 @@ impl From<Command> for String {
         s.push_str(&value.command);
         value.args.iter().for_each(|x| {
             s.push(' ');
-            s.push_str(x);
+            //arguments quoted by the caller stay quoted, so strings are never
+            //mistaken for symbols by the server
+            if x.quoted || needs_quotes(&x.value) {
+                s.push_str(&quote(&x.value));
+            } else {
+                s.push_str(&x.value);
+            }
         });
         s
     }
 }
 
 impl From<String> for Command {
-    fn from(value: String) -> Self {
-        let parts: Vec<&str> = value.split(' ').collect();
-        let command = String::from(parts[0]);
-        let args = parts.into_iter().skip(1).map(String::from).collect();
-        Command { command, args }
+    fn from(value: String) -> Self { Command::from(value.as_str()) }
+}
+
+//splits a command line into words, grouping double-quoted text into a single
+//word; a backslash takes the next character literally, inside quotes or not
+fn split_arguments(line: &str) -> Vec<Argument> {
+    let mut parts = Vec::new();
+    let mut current: Option<Argument> = None;
+    let mut in_quotes = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.get_or_insert_with(Argument::empty).quoted = true;
+            }
+            '\\' => {
+                let next = chars.next().unwrap_or('\\');
+                current.get_or_insert_with(Argument::empty).value.push(next);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if let Some(arg) = current.take() {
+                    parts.push(arg);
+                }
+            }
+            c => current.get_or_insert_with(Argument::empty).value.push(c),
+        }
     }
+    parts.extend(current);
+    parts
 }
 
-#[derive(Debug)]
+impl Argument {
+    fn empty() -> Self {
+        Argument {
+            value: String::new(),
+            quoted: false,
+        }
+    }
+}
+
+pub(super) fn needs_quotes(arg: &str) -> bool {
+    arg.is_empty() || arg.chars().any(|c| c.is_whitespace() || c == '"' || c == '\\')
+}
+
+//splits a command line into its words, with quotes and escapes resolved
+pub fn tokenize(line: &str) -> Vec<String> {
+    split_arguments(line).into_iter().map(|x| x.value).collect()
+}
+
+//wraps a string argument in double quotes, escaping what needs escaping
+pub fn quote(arg: &str) -> String {
+    let mut s = String::with_capacity(arg.len() + 2);
+    s.push('"');
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    s.push('"');
+    s
+}
+
+#[derive(Debug, Default)]
 pub enum ServerState {
+    #[default]
     Disconnected,
     Idle,
     Ready,
     Prompt,
+    Busy,
     Paused,
     Finished,
 }
 
-impl Default for ServerState {
-    fn default() -> Self { Self::Disconnected }
+//a command waiting for its turn, and every request it answers; identical
+//requests made while it waits are folded into it instead of queued again
+#[derive(Debug)]
+struct Pending {
+    tickets: Vec<Ticket>,
+    command: Command,
+    group: u64,
+}
+
+impl Pending {
+    fn new(ticket: Ticket, command: Command) -> Self {
+        Pending {
+            tickets: vec![ticket],
+            command,
+            group: 0,
+        }
+    }
+}
+
+//a command handed to the server that hasn't been answered yet
+#[derive(Debug)]
+struct InFlight {
+    tickets: Vec<Ticket>,
+    header: String,
+    sent: Instant,
+    size: usize,
+    group: u64,
 }
 
 #[derive(Default)]
 pub struct CommandQueue {
     server_state: ServerState,
     server: Option<ServerHandle>,
-    commands: VecDeque<Command>,
-    responses: VecDeque<Response>,
+    last_ticket: Ticket,
+    commands: VecDeque<Pending>,
+    in_flight: VecDeque<InFlight>,
+    abandoned: HashSet<Ticket>,
+    //answers to these are left out of the metrics
+    unmeasured: HashSet<Ticket>,
+    //one entry per answer, shared by every ticket that was merged into the command
+    responses: VecDeque<(Vec<Ticket>, Response)>,
+    hooks: Hooks,
+    handshake: Option<Handshake>,
+    capabilities: Capabilities,
+    notices: VecDeque<String>,
+    events: VecDeque<ServerEvent>,
+    keepalive: KeepAlive,
+    heartbeat: Option<Ticket>,
+    last_heard: Option<Instant>,
+    unresponsive: bool,
+    //sends every command as given, redundant or not
+    verbatim: bool,
+    batch: Option<Batch>,
+    last_group: u64,
+    undo_groups: UndoGroups,
+    metrics: Metrics,
+    stats_file: Option<PathBuf>,
 }
 
 impl CommandQueue {
     pub fn new() -> Self { Default::default() }
 
-    pub fn connect(&mut self, address: &SocketAddr) -> UnitResult {
+    //lets the owner sleep until something arrives, instead of polling
+    pub fn set_waker(&mut self, waker: impl Fn() + Send + Sync + 'static) {
+        self.hooks.waker = Some(Arc::new(waker));
+    }
+
+    //every frame sent or received from now on ends up in the recording
+    pub fn set_recorder(&mut self, recorder: Arc<Recorder>) {
+        self.hooks.recorder = Some(recorder);
+    }
+
+    pub fn set_keepalive(&mut self, keepalive: KeepAlive) { self.keepalive = keepalive; }
+
+    pub fn set_coalescing(&mut self, enabled: bool) { self.verbatim = !enabled; }
+
+    //the game sends previews as big frames, a larger game may need more room
+    pub fn set_max_frame_size(&mut self, size: usize) { self.hooks.max_frame_size = Some(size); }
+
+    //the metrics are written there as CSV when the queue is disconnected for good
+    pub fn set_stats_file(&mut self, path: PathBuf) { self.stats_file = Some(path); }
+
+    pub fn connect(&mut self, endpoint: &Endpoint) -> UnitResult {
+        if self.connected() {
+            return Err("Already connected".into());
+        }
+        self.attach(endpoint, Connection::open(endpoint)?)
+    }
+
+    //takes over a connection opened elsewhere, see Connection::open()
+    pub fn attach(&mut self, endpoint: &Endpoint, connection: Connection) -> UnitResult {
+        let hooks = self.hooks.clone();
         let r = match &mut self.server {
             Some(srv) if srv.connected => Err("Already connected".into()),
-            Some(srv) => srv.connect(address),
+            Some(srv) => srv.connect(endpoint, connection, hooks),
             None => {
                 let mut server = ServerHandle::new();
-                server.connect(address)?;
+                server.connect(endpoint, connection, hooks)?;
                 self.server = Some(server);
                 Ok(())
             }
@@ -119,25 +333,294 @@ impl CommandQueue {
         if let Some(s) = &self.server {
             if s.connected {
                 self.server_state = ServerState::Idle;
+                self.last_heard = Some(Instant::now());
+                self.begin_handshake();
             }
         }
         r
     }
 
+    //asks the server what it is before anything else gets sent
+    fn begin_handshake(&mut self) {
+        let version = self.last_ticket.next();
+        let commands = self.last_ticket.next();
+        self.commands.push_front(Pending::new(commands, Command::from(COMMANDS_QUERY)));
+        self.commands.push_front(Pending::new(version, Command::from(VERSION_QUERY)));
+        self.handshake = Some(Handshake::new(version, commands));
+        //the editor's own bookkeeping, kept out of the metrics
+        self.unmeasured.extend([version, commands]);
+        self.capabilities = Capabilities::default();
+    }
+
+    //the statistics are saved even when closing the connection fails
     pub fn disconnect(self) -> UnitResult {
-        if let Some(s) = self.server {
-            s.disconnect()?;
+        let closed = self.server.map_or(Ok(()), |s| s.disconnect());
+        let saved = match &self.stats_file {
+            Some(path) => self.metrics.save_csv(path).map_err(|e| {
+                format!("Failed to save statistics to {}: {e}", path.display()).into()
+            }),
+            None => Ok(()),
+        };
+        match (closed, saved) {
+            (Err(e), Err(f)) => Err(format!("{e}\n{f}").into()),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+            _ => Ok(()),
         }
-        Ok(())
     }
 
-    pub fn send(&mut self, command: &str) {
-        if !matches!(self.server_state, ServerState::Paused) {
-            self.commands.push_back(Command::from(command));
+    //drops the connection and everything tied to it, but keeps counting
+    //tickets so responses from the old session can't be mistaken for new ones
+    pub fn reset(&mut self) -> UnitResult {
+        self.server_state = ServerState::Disconnected;
+        self.commands.clear();
+        self.in_flight.clear();
+        self.abandoned.clear();
+        self.unmeasured.clear();
+        self.responses.clear();
+        self.handshake = None;
+        self.capabilities = Capabilities::default();
+        self.events.clear();
+        self.heartbeat = None;
+        self.last_heard = None;
+        self.unresponsive = false;
+        self.batch = None;
+        self.undo_groups.clear();
+        match self.server.take() {
+            Some(s) => s.disconnect(),
+            None => Ok(()),
         }
     }
 
-    pub fn receive(&mut self) -> Option<Response> { self.responses.pop_front() }
+    pub fn send(&mut self, command: &str) -> Ticket {
+        let command = Command::from(command);
+        let id = command.command.as_str();
+        if id == UNDO || id == REDO {
+            //the groups no longer line up with the server's history
+            self.undo_groups.clear();
+        }
+        let begin = match &mut self.batch {
+            Some(b) if b.server_side && !b.begun && commands::is_undoable(id) => {
+                b.begun = true;
+                true
+            }
+            _ => false,
+        };
+        if begin {
+            self.send_internal(BATCH_BEGIN);
+        }
+        self.enqueue(command)
+    }
+
+    fn enqueue(&mut self, command: Command) -> Ticket {
+        //nothing is handed over before the next prompt, so commands sent
+        //while the server is paused simply wait for the pause to end
+        let ticket = self.last_ticket.next();
+        match self.coalesce(&command) {
+            Some(n) => {
+                let pending = &mut self.commands[n];
+                pending.tickets.push(ticket);
+                pending.command = command;
+            }
+            None => {
+                let group = match &self.batch {
+                    Some(b) => b.group,
+                    None => self.next_group(),
+                };
+                let mut pending = Pending::new(ticket, command);
+                pending.group = group;
+                self.commands.push_back(pending);
+            }
+        }
+        ticket
+    }
+
+    //a command nobody is waiting for the answer to
+    fn send_internal(&mut self, command: &str) {
+        let ticket = self.enqueue(Command::from(command));
+        self.abandoned.insert(ticket);
+    }
+
+    //a command that hasn't been handed to the server yet is dropped, otherwise
+    //its answer is thrown away when it arrives
+    fn abandon(&mut self, ticket: Ticket) {
+        let Some(n) = self.commands.iter().position(|p| p.tickets.contains(&ticket)) else {
+            self.abandoned.insert(ticket);
+            return;
+        };
+        let pending = &mut self.commands[n];
+        pending.tickets.retain(|t| *t != ticket);
+        self.unmeasured.remove(&ticket);
+        if pending.tickets.is_empty() {
+            self.commands.remove(n);
+        }
+    }
+
+    //everything sent until the matching commit_batch() is undone in one step;
+    //batches can be nested, only the outermost one counts
+    pub fn begin_batch(&mut self) {
+        if let Some(b) = &mut self.batch {
+            b.depth += 1;
+            return;
+        }
+        let server_side =
+            self.capabilities.advertises(BATCH_BEGIN) && self.capabilities.advertises(BATCH_END);
+        self.batch = Some(Batch {
+            group: self.next_group(),
+            depth: 1,
+            server_side,
+            begun: false,
+        });
+    }
+
+    pub fn commit_batch(&mut self) {
+        let Some(b) = &mut self.batch else { return };
+        b.depth -= 1;
+        if b.depth > 0 {
+            return;
+        }
+        if let Some(Batch { begun: true, .. }) = self.batch.take() {
+            self.send_internal(BATCH_END);
+        }
+    }
+
+    //takes back the last edit, or the whole batch it was part of
+    pub fn undo(&mut self) -> Ticket {
+        let steps = if self.grouped_by_server() { 1 } else { self.undo_groups.undo() };
+        self.repeat(UNDO, steps)
+    }
+
+    pub fn redo(&mut self) -> Ticket {
+        let steps = if self.grouped_by_server() { 1 } else { self.undo_groups.redo() };
+        self.repeat(REDO, steps)
+    }
+
+    fn next_group(&mut self) -> u64 {
+        self.last_group += 1;
+        self.last_group
+    }
+
+    fn grouped_by_server(&self) -> bool { self.capabilities.advertises(BATCH_BEGIN) }
+
+    //the ticket is the one for the last repetition
+    fn repeat(&mut self, command: &str, times: usize) -> Ticket {
+        let mut ticket = self.last_ticket;
+        for _ in 0..times {
+            ticket = self.enqueue(Command::from(command));
+        }
+        ticket
+    }
+
+    //finds a pending command that makes the new one redundant: the same query,
+    //or a setter on the same target, with nothing but queries queued after it;
+    //a query answered later, or a setter overwritten sooner, goes unnoticed
+    fn coalesce(&self, command: &Command) -> Option<usize> {
+        let query = commands::is_query(&command.command);
+        let target = commands::setter_target(&command.command);
+        if self.verbatim || (!query && target.is_none()) {
+            return None;
+        }
+        for (n, pending) in self.commands.iter().enumerate().rev() {
+            let other = &pending.command;
+            if query && other == command {
+                return Some(n);
+            }
+            if let Some(len) = target {
+                let same_target = other.command == command.command
+                    && other.args.len() >= len
+                    && command.args.len() >= len
+                    && other.args[..len] == command.args[..len];
+                if same_target {
+                    return Some(n);
+                }
+            }
+            if !commands::is_query(&other.command) {
+                return None;
+            }
+        }
+        None
+    }
+
+    pub fn receive(&mut self) -> Option<(Vec<Ticket>, Response)> { self.responses.pop_front() }
+
+    //events come in between responses, but are never matched to a request
+    pub fn event(&mut self) -> Option<ServerEvent> { self.events.pop_front() }
+
+    //takes the response to one specific request, leaving the others in place
+    pub fn poll(&mut self, ticket: Ticket) -> Option<Response> {
+        let n = self.responses.iter().position(|(t, _)| t.contains(&ticket))?;
+        let (tickets, response) = &mut self.responses[n];
+        tickets.retain(|t| *t != ticket);
+        if tickets.is_empty() {
+            self.responses.remove(n).map(|(_, r)| r)
+        } else {
+            Some(response.clone())
+        }
+    }
+
+    //sends a command and blocks until its response arrives, for use outside
+    //of a frame loop; a response arriving after the timeout is discarded
+    pub fn call(&mut self, command: &str, timeout: Duration) -> Result<Response, CallError> {
+        if !self.connected() {
+            return Err(CallError::Disconnected(String::from("Not connected.")));
+        }
+        let ticket = self.send(command);
+        self.wait_for(ticket, timeout)
+    }
+
+    //like call(), for the editor's own bookkeeping, which would skew the metrics
+    pub fn call_unmeasured(
+        &mut self, command: &str, timeout: Duration,
+    ) -> Result<Response, CallError> {
+        if !self.connected() {
+            return Err(CallError::Disconnected(String::from("Not connected.")));
+        }
+        let ticket = self.send(command);
+        self.unmeasured.insert(ticket);
+        self.wait_for(ticket, timeout)
+    }
+
+    fn wait_for(&mut self, ticket: Ticket, timeout: Duration) -> Result<Response, CallError> {
+        let deadline = Instant::now() + timeout;
+        if let Err(e) = self.update() {
+            return Err(CallError::Disconnected(e.to_string()));
+        }
+        loop {
+            match self.poll(ticket) {
+                Some(r @ Response::Error(_, _)) => return Err(CallError::Server(r)),
+                Some(r) => return Ok(r),
+                None if self.finished() => {
+                    return Err(CallError::Disconnected(String::from("Server exited.")))
+                }
+                //no point in waiting out the deadline on a server that stopped answering
+                None if Instant::now() >= deadline || self.unresponsive => {
+                    self.abandon(ticket);
+                    return Err(CallError::Timeout);
+                }
+                None => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let remaining = remaining.min(self.keepalive.response_timeout);
+                    if let Err(e) = self.wait(remaining) {
+                        return Err(CallError::Disconnected(e.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    //the server answers in order and echoes the command line it received, so
+    //the oldest request with a matching echo is the one being answered
+    fn match_response(in_flight: &mut VecDeque<InFlight>, response: &Response) -> Option<InFlight> {
+        let header = normalize(response.header());
+        let n = in_flight
+            .iter()
+            .position(|c| c.header == header)
+            .or_else(|| {
+                in_flight
+                    .iter()
+                    .position(|c| c.header.split(' ').next() == Some(response.identifier()))
+            })?;
+        in_flight.remove(n)
+    }
 
     fn update_server_state(message: &str) -> Option<ServerState> {
         match message {
@@ -162,32 +645,185 @@ impl CommandQueue {
 
     pub fn finished(&self) -> bool { matches!(self.server_state, ServerState::Finished) }
 
+    //requests still waiting to be handed to the server
+    pub fn pending(&self) -> usize { self.commands.iter().map(|c| c.tickets.len()).sum() }
+
+    //connected, but the server has kept us waiting for longer than the response timeout
+    pub fn unresponsive(&self) -> bool { self.unresponsive }
+
+    //kept across reconnects, for the whole session
+    pub fn metrics(&self) -> &Metrics { &self.metrics }
+
+    //until the handshake is over, every command is assumed to be supported
+    pub fn capabilities(&self) -> &Capabilities { &self.capabilities }
+
+    pub fn diagnostic(&mut self) -> Option<String> {
+        if let Some(notice) = self.notices.pop_front() {
+            return Some(notice);
+        }
+        self.server.as_mut()?.diagnostic().map(|e| e.to_string())
+    }
+
+    //like update(), but blocks until the server says something or the timeout runs out
+    pub fn wait(&mut self, timeout: Duration) -> UnitResult {
+        let ready = self.prompt() && !self.commands.is_empty();
+        if !ready && self.responses.is_empty() {
+            if let Some(server) = &mut self.server {
+                server.wait(timeout)?;
+            }
+        }
+        self.update()
+    }
+
     pub fn update(&mut self) -> UnitResult {
         if let Some(server) = &mut self.server {
             server.update()?;
             if server.connected {
                 while let Some(response) = server.receive() {
+                    self.last_heard = Some(Instant::now());
                     let analysis = Self::analyze_response(&response);
                     if matches!(analysis, Response::Nothing) {
-                        if let Some(state) = Self::update_server_state(&response) {
+                        if let Some(event) = ServerEvent::parse(&response) {
+                            self.events.push_back(event);
+                        } else if let Some(state) = Self::update_server_state(&response) {
                             self.server_state = state;
                         } else {
-                            return Err(
-                                format!("Unrecognized message from server: {}", response).into()
-                            );
+                            //a newer server may say things this editor doesn't know about
+                            self.notices
+                                .push_back(format!("Ignored unrecognized message: {response}"));
+                        }
+                        continue;
+                    }
+                    let (err, id, _, _) = analysis.decompose();
+                    let mut matched = Self::match_response(&mut self.in_flight, &analysis);
+                    if matched.is_none() {
+                        //answers come in order, so an echo that doesn't match anything
+                        //is still the answer to the oldest request
+                        matched = self.in_flight.pop_front();
+                        if let Some(c) = &matched {
+                            let header = analysis.header();
+                            self.notices.push_back(format!(
+                                "Response to `{header}` taken as the answer to `{}`.",
+                                c.header
+                            ));
+                        }
+                    }
+                    let (tickets, group) = match matched {
+                        Some(c) => {
+                            let latency = c.sent.elapsed();
+                            let unmeasured =
+                                c.tickets.iter().filter(|t| self.unmeasured.remove(t)).count();
+                            if unmeasured < c.tickets.len() {
+                                self.metrics.record(id, latency, c.size, response.len(), err);
+                            }
+                            (c.tickets, Some(c.group))
                         }
-                    } else {
-                        self.responses.push_back(analysis);
+                        None => {
+                            let header = analysis.header();
+                            self.notices.push_back(format!("Unsolicited response to `{header}`."));
+                            (vec![Ticket::UNSOLICITED], None)
+                        }
+                    };
+                    match group {
+                        _ if err => {}
+                        //a different project comes with a history of its own
+                        _ if matches!(id, "project-new" | "project-load") => {
+                            self.undo_groups.clear()
+                        }
+                        Some(group) if commands::is_undoable(id) => self.undo_groups.record(group),
+                        _ => {}
+                    }
+                    let mut waiting = Vec::new();
+                    for ticket in tickets {
+                        if self.heartbeat == Some(ticket) {
+                            self.heartbeat = None;
+                            continue;
+                        }
+                        match &mut self.handshake {
+                            Some(h) if h.owns(ticket) => {
+                                h.receive(ticket, &analysis);
+                                if h.finished() {
+                                    if let Some(h) = self.handshake.take() {
+                                        self.capabilities = h.capabilities();
+                                    }
+                                    self.notices.extend(self.capabilities.incompatibility());
+                                }
+                            }
+                            _ => {
+                                if !self.abandoned.remove(&ticket) {
+                                    waiting.push(ticket);
+                                }
+                            }
+                        }
+                    }
+                    if !waiting.is_empty() {
+                        self.responses.push_back((waiting, analysis));
                     }
                 }
-                while let Some(command) = self.commands.pop_front() {
-                    server.send(&String::from(command))?;
+                //poke a server that has been quiet for a while, so a hang
+                //shows up as an unanswered command
+                if let Some(interval) = self.keepalive.heartbeat {
+                    let idle = self.commands.is_empty() && self.in_flight.is_empty();
+                    let quiet = self.last_heard.is_none_or(|t| t.elapsed() >= interval);
+                    let prompt = matches!(self.server_state, ServerState::Prompt);
+                    if idle && quiet && prompt && self.capabilities.supports(HEARTBEAT_COMMAND) {
+                        let ticket = self.last_ticket.next();
+                        let command = Command::from(HEARTBEAT_COMMAND);
+                        self.commands.push_back(Pending::new(ticket, command));
+                        self.heartbeat = Some(ticket);
+                        self.unmeasured.insert(ticket);
+                    }
+                }
+                //the server reads one command per prompt, so the next
+                //one is only handed over once it asks for it
+                while matches!(self.server_state, ServerState::Prompt) {
+                    let Some(Pending { tickets, command, group }) = self.commands.pop_front() else {
+                        break;
+                    };
+                    let line = String::from(command);
+                    let id = line.split_whitespace().next().unwrap_or_default();
+                    if !self.capabilities.supports(id) {
+                        //answered here, instead of letting the server choke on it
+                        let msg = format!("The server doesn't support '{id}'.");
+                        let error = Response::Error(line, msg);
+                        self.unmeasured.retain(|t| !tickets.contains(t));
+                        self.responses.push_back((tickets, error));
+                        continue;
+                    }
+                    server.send(&line)?;
+                    self.in_flight.push_back(InFlight {
+                        tickets,
+                        header: normalize(&line),
+                        sent: Instant::now(),
+                        size: line.len(),
+                        group,
+                    });
+                    self.server_state = ServerState::Busy;
                 }
+                self.check_responsiveness();
             }
         }
         Ok(())
     }
 
+    fn check_responsiveness(&mut self) {
+        //before the first prompt there's nothing in flight, but we're still waiting
+        let waiting_since = match self.server_state {
+            ServerState::Idle | ServerState::Ready => self.last_heard,
+            _ => self.in_flight.front().map(|c| c.sent),
+        };
+        let timeout = self.keepalive.response_timeout;
+        let overdue = waiting_since.is_some_and(|t| t.elapsed() >= timeout);
+        if overdue != self.unresponsive {
+            self.unresponsive = overdue;
+            self.notices.push_back(if overdue {
+                format!("Server unresponsive, nothing heard for {}s.", timeout.as_secs_f32())
+            } else {
+                String::from("Server is responding again.")
+            });
+        }
+    }
+
     fn analyze_response(response: &str) -> Response {
         const OK_SIGNAL: &str = "<OK>";
         const ERROR_SIGNAL: &str = "<ERROR>";
@@ -218,3 +854,156 @@ impl CommandQueue {
         }
     }
 }
+
+fn normalize(command: &str) -> String { command.split_whitespace().collect::<Vec<_>>().join(" ") }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARGUMENTS: [&str; 9] = [
+        "plain",
+        "two words",
+        "",
+        "\"quoted\"",
+        "back\\slash",
+        "trailing\\",
+        "mixed \"quotes\" and \\ slashes",
+        "  padded  ",
+        "tab\tand\nnewline",
+    ];
+
+    #[test]
+    fn quoted_arguments_round_trip() {
+        for arg in ARGUMENTS {
+            assert_eq!(tokenize(&quote(arg)), vec![arg], "{arg:?}");
+        }
+        let line = ARGUMENTS.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ");
+        assert_eq!(tokenize(&line), ARGUMENTS);
+    }
+
+    #[test]
+    fn split_arguments_remembers_quotes() {
+        let args = split_arguments("header-name-set \"My Track\" 12 \"7\"");
+        let quoted = args.iter().map(|a| (a.value.as_str(), a.quoted)).collect::<Vec<_>>();
+        assert_eq!(
+            quoted,
+            vec![("header-name-set", false), ("My Track", true), ("12", false), ("7", true)]
+        );
+    }
+
+    #[test]
+    fn escapes_outside_quotes() {
+        assert_eq!(tokenize("a\\ b c"), vec!["a b", "c"]);
+        assert_eq!(tokenize("\\\"x"), vec!["\"x"]);
+        assert_eq!(tokenize("  \"\"  "), vec![""]);
+        assert_eq!(tokenize(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn commands_are_requoted_as_written() {
+        for arg in ARGUMENTS {
+            let line = format!("project-load {} #t", quote(arg));
+            let command = String::from(Command::from(line.as_str()));
+            assert_eq!(command, line, "{arg:?}");
+            assert_eq!(tokenize(&command), vec!["project-load", arg, "#t"]);
+        }
+        //symbols stay bare, and only get quotes if they couldn't be read back otherwise
+        assert_eq!(String::from(Command::from("section-set 0  250 0")), "section-set 0 250 0");
+        assert_eq!(String::from(Command::from("x a\\ b")), "x \"a b\"");
+    }
+
+    //what is waiting to go out, with how many tickets each command answers
+    fn pending(queue: &CommandQueue) -> Vec<(String, usize)> {
+        let line = |p: &Pending| String::from(p.command.clone());
+        queue.commands.iter().map(|p| (line(p), p.tickets.len())).collect()
+    }
+
+    fn groups(queue: &CommandQueue) -> Vec<u64> { queue.commands.iter().map(|p| p.group).collect() }
+
+    #[test]
+    fn setters_keep_only_the_last_write_to_a_target() {
+        let mut queue = CommandQueue::new();
+        queue.send("section-set 1 100 0 0 0");
+        queue.send("section-metrics");
+        queue.send("section-set 1 200 0 0 0");
+        queue.send("section-set 2 100 0 0 0");
+        let expected = [
+            ("section-set 1 200 0 0 0", 2),
+            ("section-metrics", 1),
+            ("section-set 2 100 0 0 0", 1),
+        ];
+        assert_eq!(pending(&queue), expected.map(|(c, n)| (String::from(c), n)));
+    }
+
+    #[test]
+    fn repeated_queries_are_asked_once() {
+        let mut queue = CommandQueue::new();
+        let first = queue.send("section-list");
+        let second = queue.send("section-list");
+        assert_ne!(first, second);
+        assert_eq!(pending(&queue), vec![(String::from("section-list"), 2)]);
+        assert_eq!(queue.pending(), 2);
+    }
+
+    #[test]
+    fn edits_are_never_merged_across() {
+        let mut queue = CommandQueue::new();
+        queue.send("section-list");
+        queue.send("section-add 0");
+        queue.send("section-list");
+        queue.send("pattern-adjust 0 0");
+        queue.send("pattern-adjust 0 0");
+        queue.send("section-set 0 100 0 0 0");
+        queue.send("section-delete 1");
+        queue.send("section-set 0 200 0 0 0");
+        let merged = pending(&queue).iter().filter(|(_, n)| *n > 1).count();
+        assert_eq!((queue.commands.len(), merged), (8, 0));
+    }
+
+    #[test]
+    fn nothing_is_merged_without_coalescing() {
+        let mut queue = CommandQueue::new();
+        queue.set_coalescing(false);
+        queue.send("section-list");
+        queue.send("section-list");
+        queue.send("color-set 1 0 0 0");
+        queue.send("color-set 1 255 0 0");
+        assert_eq!(queue.commands.len(), 4);
+    }
+
+    #[test]
+    fn a_batch_is_one_group() {
+        let mut queue = CommandQueue::new();
+        queue.send("section-add 0");
+        queue.begin_batch();
+        queue.send("section-add 1");
+        queue.begin_batch();
+        queue.send("section-add 2");
+        queue.commit_batch();
+        queue.send("section-add 3");
+        queue.commit_batch();
+        queue.send("section-add 4");
+        let g = groups(&queue);
+        assert_eq!(g.len(), 5);
+        assert!(g[0] != g[1] && g[1] == g[2] && g[2] == g[3] && g[3] != g[4]);
+        //without batch-begin on the server, nothing but the edits goes out
+        assert!(pending(&queue).iter().all(|(c, _)| c.starts_with("section-add")));
+    }
+
+    #[test]
+    fn undo_repeats_for_every_edit_of_a_group() {
+        let mut queue = CommandQueue::new();
+        queue.undo_groups.record(1);
+        queue.undo_groups.record(2);
+        queue.undo_groups.record(2);
+        queue.undo();
+        assert_eq!(pending(&queue), vec![(String::from("undo"), 1), (String::from("undo"), 1)]);
+        queue.commands.clear();
+        queue.send("undo");
+        queue.commands.clear();
+        //an undo sent by hand leaves the groups out of step, so they're forgotten
+        queue.undo();
+        assert_eq!(pending(&queue), vec![(String::from("undo"), 1)]);
+    }
+}
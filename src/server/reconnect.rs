@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+const INITIAL_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+const MAX_ATTEMPTS: u32 = 10;
+
+//decides when to try connecting to the server again, doubling
+//the wait after every failure until it gives up
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: u32,
+    attempts: u32,
+    next_attempt: Instant,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self { ReconnectPolicy::new(INITIAL_DELAY, MAX_DELAY, MAX_ATTEMPTS) }
+}
+
+impl ReconnectPolicy {
+    pub fn new(initial_delay: Duration, max_delay: Duration, max_attempts: u32) -> Self {
+        ReconnectPolicy {
+            initial_delay,
+            max_delay,
+            max_attempts,
+            attempts: 0,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    pub fn due(&self) -> bool { !self.exhausted() && Instant::now() >= self.next_attempt }
+
+    pub fn exhausted(&self) -> bool { self.attempts >= self.max_attempts }
+
+    pub fn attempts(&self) -> u32 { self.attempts }
+
+    pub fn max_attempts(&self) -> u32 { self.max_attempts }
+
+    //time left until the next attempt, or None after giving up
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.exhausted() {
+            None
+        } else {
+            Some(self.next_attempt.saturating_duration_since(Instant::now()))
+        }
+    }
+
+    pub fn failed(&mut self) {
+        let factor = 2u32.saturating_pow(self.attempts);
+        let delay = self.initial_delay.saturating_mul(factor).min(self.max_delay);
+        self.attempts += 1;
+        self.next_attempt = Instant::now() + delay;
+    }
+
+    //used both after connecting and when the user asks to retry right away
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.next_attempt = Instant::now();
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use super::{encode_message, FrameDecoder, Transport, DEFAULT_MAX_FRAME_SIZE};
+use crate::utils::{GenericResult, UnitResult};
+
+const LOG_HEADER: &str = "# bride session log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+}
+
+impl Direction {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Outgoing => ">",
+            Self::Incoming => "<",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub time: f64,
+    pub direction: Direction,
+    pub message: String,
+}
+
+//writes every frame going through a connection to a file, one line each:
+//seconds since the recording started, direction, and the escaped message
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<BufWriter<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Arc<Self>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{LOG_HEADER}")?;
+        file.flush()?;
+        Ok(Arc::new(Recorder {
+            file: Mutex::new(file),
+            start: Instant::now(),
+        }))
+    }
+
+    pub fn frame(&self, direction: Direction, message: &str) {
+        let time = self.start.elapsed().as_secs_f64();
+        self.write(&format!("{time:.3} {} {}", direction.symbol(), escape(message)));
+    }
+
+    //comments are kept for whoever reads the log, and skipped on replay
+    pub fn note(&self, note: &str) { self.write(&format!("# {}", escape(note))); }
+
+    fn write(&self, line: &str) {
+        if let Ok(mut file) = self.file.lock() {
+            //a broken log shouldn't take the session down with it
+            let _ = writeln!(file, "{line}").and_then(|_| file.flush());
+        }
+    }
+}
+
+fn escape(message: &str) -> String {
+    message
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(line: &str) -> String {
+    let mut s = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, c == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some(c) => s.push(c),
+                None => s.push('\\'),
+            },
+            (c, false) => s.push(c),
+        }
+    }
+    s
+}
+
+pub fn load(path: &Path) -> GenericResult<Vec<LogEntry>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (n, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || format!("{}:{}: not a session log entry", path.display(), n + 1);
+        let mut parts = line.splitn(3, ' ');
+        let time = parts.next().and_then(|t| t.parse::<f64>().ok()).ok_or_else(invalid)?;
+        let direction = match parts.next() {
+            Some(">") => Direction::Outgoing,
+            Some("<") => Direction::Incoming,
+            _ => return Err(invalid().into()),
+        };
+        let message = unescape(parts.next().unwrap_or(""));
+        entries.push(LogEntry {
+            time,
+            direction,
+            message,
+        });
+    }
+    Ok(entries)
+}
+
+//pretty-prints a session log, for reading bug reports
+pub fn view(path: &Path) -> UnitResult {
+    let entries = load(path)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    let r = entries.iter().try_for_each(|entry| {
+        let arrow = match entry.direction {
+            Direction::Outgoing => ">>",
+            Direction::Incoming => "<<",
+        };
+        let mut lines = entry.message.lines();
+        writeln!(out, "[{:9.3}s] {} {}", entry.time, arrow, lines.next().unwrap_or(""))?;
+        lines.try_for_each(|line| writeln!(out, "{:15}{}", "", line))
+    });
+    match r.and_then(|_| out.flush()) {
+        //whoever was reading, like head, has seen enough
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        r => Ok(r?),
+    }
+}
+
+//plays the server side of a recorded session back through the usual
+//transport, answering each command with whatever followed it in the log
+#[derive(Debug, Clone)]
+pub struct Replay {
+    shared: Arc<(Mutex<ReplayState>, Condvar)>,
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    entries: VecDeque<LogEntry>,
+    readable: VecDeque<u8>,
+    written: FrameDecoder,
+    closed: bool,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> GenericResult<Self> {
+        let state = ReplayState {
+            entries: load(path)?.into(),
+            readable: VecDeque::new(),
+            written: FrameDecoder::new(DEFAULT_MAX_FRAME_SIZE),
+            closed: false,
+        };
+        Ok(Replay {
+            shared: Arc::new((Mutex::new(state), Condvar::new())),
+        })
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, ReplayState>> {
+        self.shared.0.lock().map_err(|_| poisoned())
+    }
+}
+
+fn poisoned() -> io::Error { io::Error::other("Replay state poisoned") }
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.lock()?;
+        loop {
+            if state.closed {
+                return Ok(0);
+            }
+            if !state.readable.is_empty() {
+                let n = buf.len().min(state.readable.len());
+                for (b, r) in buf.iter_mut().zip(state.readable.drain(..n)) {
+                    *b = r;
+                }
+                return Ok(n);
+            }
+            //the server only speaks up to the next recorded command, and once the
+            //log runs out it goes quiet instead of hanging up, like an idle game
+            match state.entries.front().map(|e| e.direction) {
+                Some(Direction::Incoming) => {
+                    if let Some(e) = state.entries.pop_front() {
+                        state.readable.extend(encode_message(&e.message));
+                    }
+                }
+                _ => {
+                    state = self.shared.1.wait(state).map_err(|_| poisoned())?;
+                }
+            }
+        }
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.lock()?;
+        state.written.push(buf);
+        while let Some(frame) = state.written.next_frame() {
+            let Ok(command) = frame else { continue };
+            //whatever the server said before the recorded command goes out first
+            match state.entries.iter().position(|e| e.direction == Direction::Outgoing) {
+                Some(n) => {
+                    let mut skipped = state.entries.drain(..=n).collect::<Vec<_>>();
+                    if let Some(expected) = skipped.pop() {
+                        if expected.message.split_whitespace().ne(command.split_whitespace()) {
+                            let expected = expected.message;
+                            eprintln!("[WARNING] Replay expected '{expected}', got '{command}'");
+                        }
+                    }
+                    for e in skipped {
+                        state.readable.extend(encode_message(&e.message));
+                    }
+                }
+                None => eprintln!("[WARNING] Replay log exhausted, ignoring '{command}'"),
+            }
+        }
+        self.shared.1.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+impl Transport for Replay {
+    fn try_clone(&self) -> io::Result<Self> { Ok(self.clone()) }
+
+    fn close(&self) -> io::Result<()> {
+        self.lock()?.closed = true;
+        self.shared.1.notify_all();
+        Ok(())
+    }
+}
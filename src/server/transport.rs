@@ -0,0 +1,30 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+};
+
+//a byte stream to the server; framing is the same whatever carries it
+pub trait Transport: Read + Write + Send + fmt::Debug + 'static {
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    //closes both directions, waking up anyone blocked on the stream
+    fn close(&self) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> { TcpStream::try_clone(self) }
+
+    fn close(&self) -> io::Result<()> { self.shutdown(Shutdown::Both) }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> { UnixStream::try_clone(self) }
+
+    fn close(&self) -> io::Result<()> { self.shutdown(Shutdown::Both) }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashSet;
+
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context,
+    Helper,
+};
+
+use crate::server::{commands::COMMANDS, parser::PALETTE_SIZE, quote, tokenize, Response};
+
+//commands answered by the shell itself
+const META_COMMANDS: [&str; 1] = ["stats"];
+
+//what the server said the last time the shell asked, for completing arguments
+#[derive(Debug, Default)]
+pub struct LiveData {
+    pub projects: Vec<String>,
+    pub packages: Vec<String>,
+    pub backgrounds: Vec<String>,
+    pub textures: Vec<String>,
+    pub props: Vec<String>,
+    //how many patterns each section has
+    pub patterns: Vec<usize>,
+}
+
+//the parts of the live data that are asked for separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LiveList {
+    Projects,
+    Packages,
+    //backgrounds, textures and props, which come from the loaded package
+    Assets,
+    //how many sections there are, and how many patterns each one has
+    Sections,
+    Patterns(usize),
+}
+
+impl LiveList {
+    pub fn all() -> HashSet<LiveList> {
+        HashSet::from([Self::Projects, Self::Packages, Self::Assets, Self::Sections])
+    }
+
+    //what a successful command may have changed
+    pub fn touched_by(response: &Response) -> Vec<LiveList> {
+        let (_, cmd, args, _) = response.decompose();
+        let section = tokenize(args).first().and_then(|s| s.parse::<usize>().ok());
+        match cmd {
+            "project-save" | "project-delete" => vec![Self::Projects],
+            "project-new" | "project-load" => vec![Self::Projects, Self::Assets, Self::Sections],
+            "package-load" => vec![Self::Assets],
+            "undo" | "redo" => vec![Self::Assets, Self::Sections],
+            "section-add" | "section-delete" | "section-duplicate" | "section-move" => {
+                vec![Self::Sections]
+            }
+            "pattern-add" | "pattern-delete" | "pattern-duplicate" | "pattern-copy-all" => {
+                section.map_or(vec![Self::Sections], |s| vec![Self::Patterns(s)])
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+//completes command names, and their arguments from live server data
+#[derive(Default)]
+pub struct ShellHelper {
+    pub live: LiveData,
+}
+
+impl ShellHelper {
+    //everything that fits the argument at the given position, unquoted
+    fn arguments(&self, command: &str, position: usize, args: &[String]) -> Vec<String> {
+        let Some((_, usage)) = COMMANDS.iter().find(|(c, _)| *c == command) else {
+            return Vec::new();
+        };
+        let Some(placeholder) = usage.split_whitespace().nth(position) else {
+            return Vec::new();
+        };
+        let indices = |n: usize| (0..n).map(|i| i.to_string()).collect();
+        let sections = self.live.patterns.len();
+        match placeholder.trim_matches(['[', ']']) {
+            "<section>" | "<source>" => indices(sections),
+            "<index>" if command.starts_with("pattern-") => {
+                let section = args.first().and_then(|s| s.parse::<usize>().ok());
+                indices(section.and_then(|s| self.live.patterns.get(s).copied()).unwrap_or(0))
+            }
+            "<index>" if command.starts_with("section-") => indices(sections),
+            "<index>" if command == "color-set" => indices(PALETTE_SIZE),
+            "<name>" if command.starts_with("project-") => self.live.projects.clone(),
+            "<name>" if command == "package-load" => self.live.packages.clone(),
+            "<background>" => self.live.backgrounds.clone(),
+            "<texture>" => self.live.textures.clone(),
+            "<prop>" => self.live.props.clone(),
+            "#t|#f" | "<#t|#f>" => vec![String::from("#t"), String::from("#f")],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self, line: &str, pos: usize, _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |n| n + 1);
+        let word = &before[start..];
+        let words = tokenize(&before[..start]);
+        let Some((command, args)) = words.split_first() else {
+            let names = COMMANDS.iter().map(|(c, _)| *c).chain(META_COMMANDS);
+            let matches = names.filter(|c| c.starts_with(word)).map(String::from).collect();
+            return Ok((start, matches));
+        };
+        //names are strings to the server, so they're completed with quotes
+        let prefix = word.trim_start_matches('"');
+        let matches = self
+            .arguments(command, args.len(), args)
+            .into_iter()
+            .filter(|a| a.starts_with(prefix))
+            .map(|a| if a.parse::<i64>().is_ok() || a.starts_with('#') { a } else { quote(&a) })
+            .collect();
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
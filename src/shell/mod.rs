@@ -1,6 +1,24 @@
-use std::net::SocketAddr;
+use std::{collections::HashSet, io, mem, thread, time::Duration};
 
-use crate::{server::CommandQueue, utils::UnitResult};
+use rustyline::{history::DefaultHistory, Editor};
+
+use self::completion::{LiveList, ShellHelper};
+use crate::{
+    server::{
+        commands::{CommandError, TrackCommand},
+        parser, CallError, CommandQueue, Endpoint, ReconnectPolicy, Response, ServerProcess,
+    },
+    utils::UnitResult,
+};
+
+mod completion;
+
+type ShellEditor = Editor<ShellHelper, DefaultHistory>;
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+const IDLE_WAIT: Duration = Duration::from_millis(250);
+//completion data is only worth so much waiting
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(2);
 
 enum ShellState {
     Read,
@@ -10,44 +28,106 @@ enum ShellState {
 pub struct Shell {
     queue: CommandQueue,
     state: ShellState,
+    endpoint: Endpoint,
+    reconnect: ReconnectPolicy,
+    process: Option<ServerProcess>,
+    //the parts of the completion data that may be out of date
+    stale: HashSet<LiveList>,
 }
 
 impl Shell {
-    pub fn new(port: u16) -> Self {
-        let mut queue = CommandQueue::new();
-        let _ = queue.connect(&SocketAddr::from(([127, 0, 0, 1], port)));
+    pub fn new(queue: CommandQueue, endpoint: Endpoint, process: Option<ServerProcess>) -> Self {
         Shell {
             queue,
             state: ShellState::Read,
+            endpoint,
+            reconnect: ReconnectPolicy::default(),
+            process,
+            stale: LiveList::all(),
         }
     }
 
     pub fn interactive_loop(&mut self) -> UnitResult {
-        let mut rl = rustyline::DefaultEditor::new()?;
-        let mut response_received = true;
+        let mut rl = ShellEditor::new()?;
+        rl.set_helper(Some(ShellHelper::default()));
         'interact: loop {
-            self.queue.update()?;
+            while let Some(line) = self.process.as_mut().and_then(ServerProcess::message) {
+                println!("[SERVER] {line}");
+            }
+            if !self.queue.connected() {
+                if !self.try_connect(&mut rl) {
+                    break 'interact;
+                }
+                continue 'interact;
+            }
+            //nothing to do until the server speaks, unless it's our turn to
+            let idle = matches!(self.state, ShellState::Read) && !self.queue.prompt();
+            let r = if idle {
+                self.queue.wait(IDLE_WAIT)
+            } else {
+                self.queue.update()
+            };
+            if let Err(e) = r {
+                println!("[ERROR] {e}");
+                self.drop_connection();
+                continue 'interact;
+            }
+            while let Some(msg) = self.queue.diagnostic() {
+                println!("[WARNING] {msg}");
+            }
+            while let Some(event) = self.queue.event() {
+                println!("[EVENT] {event}");
+            }
             if self.queue.finished() {
                 break 'interact;
             }
             match self.state {
                 ShellState::Read => {
-                    if let Some(msg) = self.queue.receive() {
-                        let (err, _, _, resp) = msg.decompose();
-                        let status = if err { "ERROR" } else { "OK" };
-                        println!("[{}] {}", status, resp);
-                        response_received = true;
-                    } else if self.queue.prompt() && response_received {
+                    if let Some((_, msg)) = self.queue.receive() {
+                        Self::print_response(&msg);
+                    } else if self.queue.prompt() || self.queue.unresponsive() {
+                        //don't leave the user stuck waiting on a hung server
                         self.state = ShellState::Write;
                     }
                 }
                 ShellState::Write => {
-                    let read = rl.readline("bride> ");
+                    if !self.stale.is_empty() && !self.queue.unresponsive() {
+                        self.refresh(&mut rl);
+                    }
+                    let read = if self.queue.unresponsive() {
+                        rl.readline("bride (server unresponsive)> ")
+                    } else {
+                        rl.readline("bride> ")
+                    };
                     if let Ok(line) = read {
                         rl.add_history_entry(line.as_str())?;
-                        self.queue.send(&line);
+                        //answered by the shell itself, the server never sees it
+                        if line.trim() == "stats" {
+                            println!("{}", self.queue.metrics().report());
+                            continue 'interact;
+                        }
+                        let command = match Self::parse(line) {
+                            Ok(cmd) => cmd,
+                            Err(e) => {
+                                println!("[ERROR] {e}");
+                                continue 'interact;
+                            }
+                        };
+                        match self.queue.call(&command.to_string(), CALL_TIMEOUT) {
+                            Ok(msg) => {
+                                self.stale.extend(LiveList::touched_by(&msg));
+                                Self::print_response(&msg);
+                            }
+                            Err(CallError::Server(msg)) => Self::print_response(&msg),
+                            Err(CallError::Timeout) => println!("[ERROR] {}", CallError::Timeout),
+                            Err(_) if self.queue.finished() => break 'interact,
+                            Err(e) => {
+                                println!("[ERROR] {e}");
+                                self.drop_connection();
+                                continue 'interact;
+                            }
+                        }
                         self.state = ShellState::Read;
-                        response_received = false;
                     } else {
                         break 'interact;
                     }
@@ -57,8 +137,240 @@ impl Shell {
         Ok(())
     }
 
-    pub fn shutdown(self) -> UnitResult {
+    //runs every line in order, the way it would have been typed; blank lines and
+    //lines starting with # are skipped; fails if any of the commands did
+    pub fn run_script(
+        &mut self, lines: impl IntoIterator<Item = io::Result<String>>, fail_fast: bool,
+    ) -> UnitResult {
+        self.connect()?;
+        let mut failures = 0;
+        let mut lines = lines.into_iter().enumerate();
+        while let Some((n, line)) = lines.next() {
+            let line = line?;
+            let line = line.trim();
+            if !Self::is_command(line) {
+                continue;
+            }
+            println!("bride> {line}");
+            if line == "stats" {
+                println!("{}", self.queue.metrics().report());
+                continue;
+            }
+            let ok = match Self::parse(String::from(line)) {
+                Ok(command) => match self.execute(&command.to_string()) {
+                    Some(ok) => ok,
+                    None => {
+                        //the rest of the script never ran, which is no success
+                        let left = lines.by_ref().filter_map(|(_, l)| l.ok());
+                        let skipped = left.filter(|l| Self::is_command(l.trim())).count();
+                        if skipped > 0 {
+                            println!("[ERROR] Stopped at line {}, the server exited.", n + 1);
+                            failures += skipped;
+                        }
+                        break;
+                    }
+                },
+                Err(e) => {
+                    println!("[ERROR] {e}");
+                    false
+                }
+            };
+            while let Some(line) = self.process.as_mut().and_then(ServerProcess::message) {
+                println!("[SERVER] {line}");
+            }
+            while let Some(msg) = self.queue.diagnostic() {
+                println!("[WARNING] {msg}");
+            }
+            while let Some(event) = self.queue.event() {
+                println!("[EVENT] {event}");
+            }
+            if !ok {
+                failures += 1;
+                if fail_fast {
+                    return Err(format!("Stopped at line {}.", n + 1).into());
+                }
+            }
+            if !self.queue.connected() {
+                return Err("Lost the connection to the server.".into());
+            }
+        }
+        Self::failed(failures)
+    }
+
+    //runs each command exactly as given, printing nothing but how it went
+    pub fn run_commands(&mut self, commands: &[String], fail_fast: bool) -> UnitResult {
+        self.connect()?;
+        let mut failures = 0;
+        for (n, command) in commands.iter().enumerate() {
+            let Some(ok) = self.execute(command) else {
+                //whatever was left never ran, which is no success
+                let skipped = commands.len() - n - 1;
+                if skipped > 0 {
+                    println!("[ERROR] Stopped at command {}, the server exited.", n + 1);
+                    failures += skipped;
+                }
+                break;
+            };
+            if !ok {
+                failures += 1;
+                if fail_fast {
+                    return Err(format!("Stopped at command {}.", n + 1).into());
+                }
+            }
+            if !self.queue.connected() {
+                return Err("Lost the connection to the server.".into());
+            }
+        }
+        Self::failed(failures)
+    }
+
+    //sends one command and prints the response; None once the server has exited
+    fn execute(&mut self, command: &str) -> Option<bool> {
+        match self.queue.call(command, CALL_TIMEOUT) {
+            Ok(msg) => {
+                Self::print_response(&msg);
+                Some(true)
+            }
+            Err(CallError::Server(msg)) => {
+                Self::print_response(&msg);
+                Some(false)
+            }
+            Err(_) if self.queue.finished() => {
+                println!("[OK] The server exited.");
+                None
+            }
+            Err(e) => {
+                println!("[ERROR] {e}");
+                Some(false)
+            }
+        }
+    }
+
+    //blank lines and comments in a script are not commands
+    fn is_command(line: &str) -> bool { !line.is_empty() && !line.starts_with('#') }
+
+    fn failed(failures: usize) -> UnitResult {
+        match failures {
+            0 => Ok(()),
+            1 => Err("1 command failed.".into()),
+            n => Err(format!("{n} commands failed.").into()),
+        }
+    }
+
+    //like try_connect(), but without anyone around to ask whether to keep trying
+    fn connect(&mut self) -> UnitResult {
+        while let Err(e) = self.queue.connect(&self.endpoint) {
+            self.reconnect.failed();
+            match self.reconnect.remaining() {
+                Some(t) => {
+                    let s = t.as_secs_f32();
+                    println!("[ERROR] Failed to connect to server: {e} (retrying in {s:.1}s)");
+                    thread::sleep(t);
+                }
+                None => {
+                    let attempts = self.reconnect.attempts();
+                    return Err(format!("Server unavailable after {attempts} attempts: {e}").into());
+                }
+            }
+        }
+        self.reconnect.reset();
+        Ok(())
+    }
+
+    //asks the server again for whatever arguments are completed from that
+    //may have changed; whatever can't be had is simply left out
+    fn refresh(&mut self, rl: &mut ShellEditor) {
+        let Some(helper) = rl.helper_mut() else { return };
+        let live = &mut helper.live;
+        let stale = mem::take(&mut self.stale);
+        let mut names = |command: &str| match self.queue.call_unmeasured(command, REFRESH_TIMEOUT) {
+            Ok(r) => parser::names(&r),
+            Err(_) => Vec::new(),
+        };
+        for list in &stale {
+            match *list {
+                LiveList::Projects => live.projects = names("project-list"),
+                LiveList::Packages => live.packages = names("package-list"),
+                LiveList::Assets => {
+                    live.backgrounds = names("package-backgrounds");
+                    live.textures = names("package-textures");
+                    live.props = names("package-props");
+                }
+                LiveList::Sections => {
+                    let sections = names("section-list").len();
+                    live.patterns =
+                        (0..sections).map(|i| names(&format!("pattern-list {i}")).len()).collect();
+                }
+                LiveList::Patterns(_) => {}
+            }
+        }
+        //a single section is only worth asking about when the others weren't
+        if !stale.contains(&LiveList::Sections) {
+            for list in &stale {
+                if let LiveList::Patterns(i) = *list {
+                    if i < live.patterns.len() {
+                        live.patterns[i] = names(&format!("pattern-list {i}")).len();
+                    }
+                }
+            }
+        }
+    }
+
+    //commands the editor doesn't know about are passed through as they are
+    fn parse(line: String) -> Result<TrackCommand, CommandError> {
+        match line.parse::<TrackCommand>() {
+            Err(CommandError::Unknown(_)) => Ok(TrackCommand::Raw(line)),
+            r => r,
+        }
+    }
+
+    //returns false if the user gave up on the server
+    fn try_connect(&mut self, rl: &mut ShellEditor) -> bool {
+        if self.reconnect.exhausted() {
+            let attempts = self.reconnect.attempts();
+            println!("[ERROR] Server unavailable after {attempts} attempts.");
+            if rl.readline("Press Enter to try again, or Ctrl+D to quit. ").is_err() {
+                return false;
+            }
+            self.reconnect.reset();
+        }
+        if !self.reconnect.due() {
+            thread::sleep(self.reconnect.remaining().unwrap_or_default());
+        } else if let Err(e) = self.queue.connect(&self.endpoint) {
+            self.reconnect.failed();
+            if let Some(t) = self.reconnect.remaining() {
+                let t = t.as_secs_f32();
+                println!("[ERROR] Failed to connect to server: {e} (retrying in {t:.1}s)");
+            }
+        } else {
+            self.reconnect.reset();
+            self.state = ShellState::Read;
+            self.stale = LiveList::all();
+            println!("[OK] Connected to server.");
+        }
+        true
+    }
+
+    fn drop_connection(&mut self) {
+        self.state = ShellState::Read;
+        self.reconnect.reset();
+        //the connection is already gone, so errors here are only worth a mention
+        if let Err(e) = self.queue.reset() {
+            println!("[ERROR] {e}");
+        }
+    }
+
+    fn print_response(msg: &Response) {
+        let (err, _, _, resp) = msg.decompose();
+        let status = if err { "ERROR" } else { "OK" };
+        println!("[{}] {}", status, resp);
+    }
+
+    pub fn shutdown(mut self) -> UnitResult {
         self.queue.disconnect()?;
+        if let Some(process) = &mut self.process {
+            process.stop()?;
+        }
         Ok(())
     }
 }